@@ -0,0 +1,443 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Row};
+
+use crate::client::results::Timing;
+use crate::installation::Installation;
+
+/// YXML markers, see the Isabelle System Manual
+const X: char = '\u{5}';
+const Y: char = '\u{6}';
+
+/// Magic number of XZ compressed data
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0];
+
+/// Time spent on a theory during a session build.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TheoryTiming {
+    /// The session-qualified theory name, e.g., `HOL-Library.Multiset`
+    pub theory: String,
+    pub timing: Timing,
+}
+
+/// A sample of the ML runtime statistics, taken periodically during a session build.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MlStatistics {
+    /// Time since the start of the ML process in seconds
+    pub time: f64,
+    /// Numeric values by name, e.g., `size_heap` or `threads_in_ML`
+    pub values: BTreeMap<String, f64>,
+}
+
+/// Build results of a session as recorded by Isabelle in its log database (`log/SESSION.db`)
+/// and its compressed log file (`log/SESSION.gz`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionLog {
+    pub session: String,
+    /// Overall timing of the build, if it finished
+    pub timing: Option<Timing>,
+    /// Number of threads the session was built with
+    pub threads: Option<usize>,
+    /// Return code of the build, only recorded in the log database
+    pub return_code: Option<i64>,
+    pub theory_timings: Vec<TheoryTiming>,
+    pub ml_statistics: Vec<MlStatistics>,
+    /// Error messages as plain text
+    pub errors: Vec<String>,
+    /// Lines of the build output, without the embedded protocol messages
+    pub log: Vec<String>,
+}
+
+impl SessionLog {
+    /// Returns whether the build succeeded, if known.
+    pub fn ok(&self) -> Option<bool> {
+        self.return_code.map(|rc| rc == 0)
+    }
+}
+
+/// Reads the build log of `session` from the heaps of the default installation, see [read_session_log_with].
+pub fn read_session_log(session: &str) -> io::Result<SessionLog> {
+    read_session_log_with(&Installation::default(), session)
+}
+
+/// Reads the build log of `session` from the heaps of `installation`,
+/// i.e., from `$ISABELLE_HEAPS/$ML_IDENTIFIER/log`, falling back to the system heaps (`$ISABELLE_HEAPS_SYSTEM`).
+///
+/// Returns an error of kind [io::ErrorKind::NotFound] if no log of the session exists.
+pub fn read_session_log_with(installation: &Installation, session: &str) -> io::Result<SessionLog> {
+    let ml_identifier = installation.getenv("ML_IDENTIFIER")?;
+    for heaps in ["ISABELLE_HEAPS", "ISABELLE_HEAPS_SYSTEM"] {
+        let dir = PathBuf::from(installation.getenv(heaps)?)
+            .join(&ml_identifier)
+            .join("log");
+        match read_session_log_from(&dir, session) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            res => return res,
+        }
+    }
+    Err(not_found(session))
+}
+
+/// Reads the build log of `session` from the log directory `dir`, e.g., `~/.isabelle/heaps/polyml-5.9_x86_64_32-linux/log`.
+///
+/// Values recorded in the log database take precedence over those in the log file.
+/// Returns an error of kind [io::ErrorKind::NotFound] if neither exists.
+pub fn read_session_log_from(dir: &Path, session: &str) -> io::Result<SessionLog> {
+    let db = dir.join(format!("{}.db", session));
+    let gz = dir.join(format!("{}.gz", session));
+    if !db.exists() && !gz.exists() {
+        return Err(not_found(session));
+    }
+    let mut log = SessionLog {
+        session: session.to_owned(),
+        ..Default::default()
+    };
+    if db.exists() {
+        read_db(&db, &mut log)?;
+    }
+    if gz.exists() {
+        read_gz(&gz, &mut log)?;
+    }
+    Ok(log)
+}
+
+fn not_found(session: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No build log for session {}", session),
+    )
+}
+
+/// Reads the `isabelle_session_info` table of the log database.
+fn read_db(db: &Path, log: &mut SessionLog) -> io::Result<()> {
+    let con = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(io::Error::other)?;
+    let row = con
+        .query_row(
+            "SELECT * FROM isabelle_session_info WHERE session_name = ?1",
+            [&log.session],
+            |row| {
+                let blob = |name| column::<Vec<u8>>(row, name);
+                Ok((
+                    blob("session_timing")?,
+                    blob("theory_timings")?,
+                    blob("ml_statistics")?,
+                    blob("errors")?,
+                    column::<i64>(row, "return_code")?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(io::Error::other)?;
+    let Some((session_timing, theory_timings, ml_stats, errors, return_code)) = row else {
+        return Ok(());
+    };
+
+    if let Some(props) = session_timing.map(decode).transpose()? {
+        set_session_timing(log, &properties(&props));
+    }
+    if let Some(body) = theory_timings.map(decode).transpose()? {
+        log.theory_timings = list(&body)
+            .iter()
+            .filter_map(|p| theory_timing(&properties(p)))
+            .collect();
+    }
+    if let Some(body) = ml_stats.map(decode).transpose()? {
+        log.ml_statistics = list(&body)
+            .iter()
+            .filter_map(|p| ml_statistics(&properties(p)))
+            .collect();
+    }
+    if let Some(body) = errors.map(decode).transpose()? {
+        log.errors = list(&body).iter().map(|e| content(e)).collect();
+    }
+    log.return_code = return_code;
+    Ok(())
+}
+
+/// Returns the value of the column `name`, or `None` if it is null or does not exist in this Isabelle version.
+fn column<T: rusqlite::types::FromSql>(row: &Row, name: &str) -> rusqlite::Result<Option<T>> {
+    match row.get::<_, Option<T>>(name) {
+        Err(rusqlite::Error::InvalidColumnName(_)) => Ok(None),
+        res => res,
+    }
+}
+
+/// Reads the compressed log file, whose protocol messages are lines of the form `\fName = YXML`,
+/// e.g., `\fSession_Timing = ...`. Names are matched case-insensitively, as they differ between Isabelle versions.
+fn read_gz(gz: &Path, log: &mut SessionLog) -> io::Result<()> {
+    let mut theory_timings = Vec::new();
+    let mut ml_stats = Vec::new();
+    let mut errors = Vec::new();
+    let mut timing = None;
+    for line in BufReader::new(GzDecoder::new(File::open(gz)?)).lines() {
+        let line = line?;
+        let Some((name, value)) = line.strip_prefix('\u{c}').and_then(|l| l.split_once(" = "))
+        else {
+            log.log.push(line);
+            continue;
+        };
+        let body = parse_yxml(value);
+        match name.to_ascii_lowercase().as_str() {
+            "timing" | "session_timing" => timing = Some(properties(&body)),
+            "theory_timing" => theory_timings.extend(theory_timing(&properties(&body))),
+            "ml_statistics" => ml_stats.extend(ml_statistics(&properties(&body))),
+            "error_message" => errors.push(content(&body)),
+            _ => {}
+        }
+    }
+    // The database is more reliable, as the log file may be truncated
+    if log.timing.is_none() {
+        if let Some(props) = timing {
+            set_session_timing(log, &props);
+        }
+    }
+    if log.theory_timings.is_empty() {
+        log.theory_timings = theory_timings;
+    }
+    if log.ml_statistics.is_empty() {
+        log.ml_statistics = ml_stats;
+    }
+    if log.errors.is_empty() {
+        log.errors = errors;
+    }
+    Ok(())
+}
+
+/// Decodes a (possibly XZ compressed) YXML blob.
+fn decode(blob: Vec<u8>) -> io::Result<Vec<Tree>> {
+    let bytes = if blob.starts_with(XZ_MAGIC) {
+        let mut out = Vec::new();
+        lzma_rs::xz_decompress(&mut &blob[..], &mut out).map_err(io::Error::other)?;
+        out
+    } else {
+        blob
+    };
+    Ok(parse_yxml(&String::from_utf8_lossy(&bytes)))
+}
+
+fn set_session_timing(log: &mut SessionLog, props: &BTreeMap<String, String>) {
+    log.timing = timing(props);
+    log.threads = props.get("threads").and_then(|t| t.parse().ok());
+}
+
+fn timing(props: &BTreeMap<String, String>) -> Option<Timing> {
+    let value = |name| props.get(name).and_then(|v| v.parse().ok());
+    Some(Timing {
+        elapsed: value("elapsed")?,
+        cpu: value("cpu").unwrap_or_default(),
+        gc: value("gc").unwrap_or_default(),
+    })
+}
+
+fn theory_timing(props: &BTreeMap<String, String>) -> Option<TheoryTiming> {
+    Some(TheoryTiming {
+        theory: props.get("name")?.clone(),
+        timing: timing(props)?,
+    })
+}
+
+fn ml_statistics(props: &BTreeMap<String, String>) -> Option<MlStatistics> {
+    let mut values = props
+        .iter()
+        .filter_map(|(k, v)| Some((k.clone(), v.parse().ok()?)))
+        .collect::<BTreeMap<String, f64>>();
+    Some(MlStatistics {
+        time: values.remove("time")?,
+        values,
+    })
+}
+
+/// An XML tree as encoded in YXML.
+#[derive(Debug, Clone, PartialEq)]
+enum Tree {
+    Elem(Vec<Tree>),
+    Text(String),
+}
+
+/// Parses a YXML body, dropping element names and attributes.
+fn parse_yxml(s: &str) -> Vec<Tree> {
+    let mut stack = vec![Vec::new()];
+    for (i, chunk) in s.split(X).enumerate() {
+        // Chunks at odd positions are markup
+        if i % 2 == 1 && chunk.starts_with(Y) {
+            if chunk.len() == Y.len_utf8() {
+                if stack.len() > 1 {
+                    let children = stack.pop().unwrap();
+                    stack.last_mut().unwrap().push(Tree::Elem(children));
+                }
+            } else {
+                stack.push(Vec::new());
+            }
+        } else if !chunk.is_empty() {
+            stack.last_mut().unwrap().push(Tree::Text(chunk.to_owned()));
+        }
+    }
+    // Close unterminated elements
+    while stack.len() > 1 {
+        let children = stack.pop().unwrap();
+        stack.last_mut().unwrap().push(Tree::Elem(children));
+    }
+    stack.pop().unwrap()
+}
+
+/// Returns the text content of `body`.
+fn content(body: &[Tree]) -> String {
+    body.iter()
+        .map(|t| match t {
+            Tree::Elem(children) => content(children),
+            Tree::Text(text) => text.clone(),
+        })
+        .collect()
+}
+
+/// Decodes a list, whose elements are encoded as the bodies of consecutive elements.
+fn list(body: &[Tree]) -> Vec<Vec<Tree>> {
+    body.iter()
+        .filter_map(|t| match t {
+            Tree::Elem(children) => Some(children.clone()),
+            Tree::Text(_) => None,
+        })
+        .collect()
+}
+
+/// Decodes properties, i.e., a list of pairs of strings.
+fn properties(body: &[Tree]) -> BTreeMap<String, String> {
+    list(body)
+        .iter()
+        .filter_map(|pair| match list(pair).as_slice() {
+            [k, v] => Some((content(k), content(v))),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    /// Encodes properties as YXML.
+    fn yxml(props: &[(&str, &str)]) -> String {
+        let node = |body: String| format!("{X}{Y}:{X}{body}{X}{Y}{X}");
+        props
+            .iter()
+            .map(|(k, v)| node(node(k.to_string()) + &node(v.to_string())))
+            .collect()
+    }
+
+    fn xz(s: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        lzma_rs::xz_compress(&mut s.as_bytes(), &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_parse_yxml() {
+        let body = parse_yxml(&yxml(&[("elapsed", "1.5"), ("name", "")]));
+        let props = properties(&body);
+        assert_eq!(props["elapsed"], "1.5");
+        assert_eq!(props["name"], "");
+
+        let msg = format!("Undefined fact: {X}{Y}literal{Y}kind=fact{X}\"foo\"{X}{Y}{X}");
+        assert_eq!(content(&parse_yxml(&msg)), "Undefined fact: \"foo\"");
+    }
+
+    #[test]
+    fn test_read_session_log() {
+        let dir =
+            std::env::temp_dir().join(crate::server::unique_name("isabelle-client-build-log"));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(
+            read_session_log_from(&dir, "Foo").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let session_timing = [
+            ("threads", "8"),
+            ("elapsed", "83.125"),
+            ("cpu", "296.5"),
+            ("gc", "12.25"),
+        ];
+        let theory = yxml(&[
+            ("name", "Foo.Bar"),
+            ("elapsed", "2.5"),
+            ("cpu", "4.0"),
+            ("gc", "0.5"),
+        ]);
+        let errors = format!("{X}{Y}:{X}Failed to finish proof{X}{Y}{X}");
+        let con = Connection::open(dir.join("Foo.db")).unwrap();
+        con.execute_batch(
+            "CREATE TABLE isabelle_session_info (session_name TEXT, session_timing BLOB, \
+             theory_timings BLOB, ml_statistics BLOB, errors BLOB, return_code INTEGER)",
+        )
+        .unwrap();
+        con.execute(
+            "INSERT INTO isabelle_session_info VALUES ('Foo', ?1, ?2, NULL, ?3, 1)",
+            rusqlite::params![
+                yxml(&session_timing).into_bytes(),
+                xz(&format!("{X}{Y}:{X}{theory}{X}{Y}{X}")),
+                xz(&errors),
+            ],
+        )
+        .unwrap();
+
+        let mut gz = GzEncoder::new(
+            File::create(dir.join("Foo.gz")).unwrap(),
+            Compression::default(),
+        );
+        writeln!(gz, "Loading theory \"Foo.Bar\"").unwrap();
+        writeln!(
+            gz,
+            "\u{c}ML_Statistics = {}",
+            yxml(&[("time", "1.0"), ("size_heap", "1024"), ("now", "x")])
+        )
+        .unwrap();
+        writeln!(
+            gz,
+            "\u{c}Error_Message = ignored, as the database has errors"
+        )
+        .unwrap();
+        gz.finish().unwrap();
+
+        let log = read_session_log_from(&dir, "Foo").unwrap();
+        assert_eq!(log.ok(), Some(false));
+        assert_eq!(log.threads, Some(8));
+        assert_eq!(log.timing.unwrap().elapsed, 83.125);
+        assert_eq!(log.timing.unwrap().gc, 12.25);
+        assert_eq!(log.theory_timings.len(), 1);
+        assert_eq!(log.theory_timings[0].theory, "Foo.Bar");
+        assert_eq!(log.theory_timings[0].timing.cpu, 4.0);
+        assert_eq!(log.errors, vec!["Failed to finish proof"]);
+        assert_eq!(log.ml_statistics.len(), 1);
+        assert_eq!(log.ml_statistics[0].time, 1.0);
+        assert_eq!(log.ml_statistics[0].values["size_heap"], 1024.0);
+        assert!(!log.ml_statistics[0].values.contains_key("now"));
+        assert_eq!(log.log, vec!["Loading theory \"Foo.Bar\""]);
+
+        // Without a database, everything is read from the log file
+        let mut gz = GzEncoder::new(
+            File::create(dir.join("Bar.gz")).unwrap(),
+            Compression::default(),
+        );
+        writeln!(gz, "\u{c}Theory_Timing = {}", theory).unwrap();
+        writeln!(gz, "\u{c}Error_Message = {}", errors).unwrap();
+        writeln!(gz, "\u{c}Session_Timing = {}", yxml(&session_timing)).unwrap();
+        gz.finish().unwrap();
+
+        let log = read_session_log_from(&dir, "Bar").unwrap();
+        assert_eq!(log.ok(), None);
+        assert_eq!(log.threads, Some(8));
+        assert_eq!(log.timing.unwrap().cpu, 296.5);
+        assert_eq!(log.theory_timings[0].theory, "Foo.Bar");
+        assert_eq!(log.errors, vec!["Failed to finish proof"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -6,8 +6,55 @@ pub struct CancelArgs {
     pub task: String,
 }
 
-/// Arguments for `session_build` and `session_start` commands
-#[derive(Deserialize, Serialize, Default, Debug)]
+/// Builder methods for the arguments shared by `session_build` and `session_start`,
+/// where `$($path).*` leads from `self` to the [SessionBuildArgs].
+macro_rules! session_build_builders {
+    ($($path:ident).*) => {
+        /// Sets the `preferences` from which the environment of Isabelle system options is determined.
+        pub fn preferences(mut self, preferences: &str) -> Self {
+            self$(.$path)*.preferences = Some(preferences.to_owned());
+            self
+        }
+
+        /// Adds updates to Isabelle system options of the form `name=value` or `name`.
+        pub fn options<S: Into<String>>(mut self, options: impl IntoIterator<Item = S>) -> Self {
+            self$(.$path)*
+                .options
+                .get_or_insert_with(Vec::new)
+                .extend(options.into_iter().map(Into::into));
+            self
+        }
+
+        /// Adds directories for session `ROOT` and `ROOTS` files.
+        pub fn dirs<S: Into<String>>(mut self, dirs: impl IntoIterator<Item = S>) -> Self {
+            self$(.$path)*
+                .dirs
+                .get_or_insert_with(Vec::new)
+                .extend(dirs.into_iter().map(Into::into));
+            self
+        }
+
+        /// Adds sessions whose theories should be included in the overall name space.
+        pub fn include_sessions<S: Into<String>>(
+            mut self,
+            sessions: impl IntoIterator<Item = S>,
+        ) -> Self {
+            self$(.$path)*
+                .include_sessions
+                .extend(sessions.into_iter().map(Into::into));
+            self
+        }
+
+        /// Enables or disables verbose build output.
+        pub fn verbose(mut self, verbose: bool) -> Self {
+            self$(.$path)*.verbose = Some(verbose);
+            self
+        }
+    };
+}
+
+/// Arguments for `session_build` command
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SessionBuildArgs {
     /// Specifies the target session name. The build process will produce all required ancestor images according to the overall session graph.
     pub session: String,
@@ -22,8 +69,12 @@ pub struct SessionBuildArgs {
     pub dirs: Option<Vec<String>>,
     /// Specifies sessions whose theories should be included in the overall name space of session-qualified theory names.
     /// Corresponds to `session` specification in `ROOT` files.
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub include_sessions: Vec<String>,
+    /// Enables more verbose output during the build process.
+    /// The output is delivered as notes of the running task, see [crate::client::AsyncTask::notes].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbose: Option<bool>,
 }
 
 impl SessionBuildArgs {
@@ -33,6 +84,39 @@ impl SessionBuildArgs {
             ..Default::default()
         }
     }
+
+    session_build_builders!();
+}
+
+/// Arguments for `session_start` command.
+/// The server builds the session image on demand, so all arguments of `session_build` apply.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionStartArgs {
+    /// Arguments for building the session image, including the target session name
+    #[serde(flatten)]
+    pub build: SessionBuildArgs,
+    /// Specifies the print modes used for the Isabelle/PIDE session, e.g., `ASCII`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub print_mode: Option<Vec<String>>,
+}
+
+impl SessionStartArgs {
+    pub fn session(session: &str) -> Self {
+        Self {
+            build: SessionBuildArgs::session(session),
+            ..Default::default()
+        }
+    }
+
+    session_build_builders!(build);
+
+    /// Adds print modes for the session.
+    pub fn print_mode<S: Into<String>>(mut self, modes: impl IntoIterator<Item = S>) -> Self {
+        self.print_mode
+            .get_or_insert_with(Vec::new)
+            .extend(modes.into_iter().map(Into::into));
+        self
+    }
 }
 
 /// Arguments for `session_stop` command
@@ -93,3 +177,41 @@ impl PurgeTheoryArgs {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_start_args_serialization() {
+        let args = SessionStartArgs::session("HOL")
+            .dirs(["/foo"])
+            .print_mode(["ASCII"]);
+        assert_eq!(
+            serde_json::to_string(&args).unwrap(),
+            r#"{"session":"HOL","dirs":["/foo"],"print_mode":["ASCII"]}"#
+        );
+
+        let args = SessionStartArgs::session("HOL").verbose(true);
+        assert_eq!(
+            serde_json::to_string(&args).unwrap(),
+            r#"{"session":"HOL","verbose":true}"#
+        );
+        let parsed: SessionStartArgs =
+            serde_json::from_str(r#"{"session":"HOL","verbose":true,"print_mode":["ASCII"]}"#)
+                .unwrap();
+        assert_eq!(parsed, args.print_mode(["ASCII"]));
+    }
+
+    #[test]
+    fn test_session_build_args_serialization() {
+        let args = SessionBuildArgs::session("HOL")
+            .options(["threads=4"])
+            .include_sessions(["HOL-Library"])
+            .verbose(true);
+        assert_eq!(
+            serde_json::to_string(&args).unwrap(),
+            r#"{"session":"HOL","options":["threads=4"],"include_sessions":["HOL-Library"],"verbose":true}"#
+        );
+    }
+}
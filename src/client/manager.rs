@@ -0,0 +1,416 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::Notify;
+
+use super::{args::*, results::*, session::Session, AsyncResult, IsabelleClient, SyncResult};
+
+/// Identifies sessions that can be shared between users.
+/// It consists of all arguments of `session_start` that affect the started session.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionKey {
+    pub session: String,
+    pub preferences: Option<String>,
+    pub dirs: Vec<String>,
+    pub options: Vec<String>,
+    pub include_sessions: Vec<String>,
+    pub print_mode: Vec<String>,
+}
+
+impl From<&SessionStartArgs> for SessionKey {
+    fn from(args: &SessionStartArgs) -> Self {
+        Self {
+            session: args.build.session.clone(),
+            preferences: args.build.preferences.clone(),
+            dirs: args.build.dirs.clone().unwrap_or_default(),
+            options: args.build.options.clone().unwrap_or_default(),
+            include_sessions: args.build.include_sessions.clone(),
+            print_mode: args.print_mode.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Configuration of a [SessionManager].
+#[derive(Debug, Clone)]
+pub struct SessionManagerConfig {
+    /// Maximum number of sessions that are alive at the same time, idle or in use.
+    pub max_sessions: usize,
+    /// Number of times a session is handed out before it is stopped and replaced by a fresh one (0 = unlimited).
+    pub max_uses: usize,
+}
+
+impl Default for SessionManagerConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: 4,
+            max_uses: 0,
+        }
+    }
+}
+
+struct PooledSession {
+    session: Session,
+    uses: usize,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// Sessions that are alive but not handed out
+    idle: HashMap<SessionKey, Vec<PooledSession>>,
+    /// Number of sessions that are alive or being started
+    live: usize,
+}
+
+struct ManagerInner {
+    client: IsabelleClient,
+    config: SessionManagerConfig,
+    state: Mutex<PoolState>,
+    released: Notify,
+}
+
+/// Keeps a pool of warm sessions and hands them out for `use_theories`.
+///
+/// Sessions are keyed by the arguments they were started with ([SessionKey]).
+/// When a session is returned to the pool, all of its theories are purged.
+/// A session is stopped instead of being returned if it has been handed out `max_uses` times or if an error occurred while it was in use.
+/// At most `max_sessions` sessions are alive at any time, idle sessions of other keys are stopped to make room for new ones.
+#[derive(Clone)]
+pub struct SessionManager {
+    inner: Arc<ManagerInner>,
+}
+
+/// What [SessionManager::checkout] does after inspecting the pool.
+enum Checkout {
+    Reuse(PooledSession),
+    Start(Option<PooledSession>),
+    Wait,
+}
+
+impl SessionManager {
+    /// Creates a new session manager starting sessions with `client`.
+    pub fn new(client: IsabelleClient, config: SessionManagerConfig) -> Self {
+        Self {
+            inner: Arc::new(ManagerInner {
+                client,
+                config: SessionManagerConfig {
+                    max_sessions: config.max_sessions.max(1),
+                    ..config
+                },
+                state: Mutex::new(PoolState::default()),
+                released: Notify::new(),
+            }),
+        }
+    }
+
+    /// Returns the number of live sessions.
+    pub fn live(&self) -> usize {
+        self.inner.state.lock().unwrap().live
+    }
+
+    /// Returns the number of idle sessions.
+    pub fn idle(&self) -> usize {
+        let state = self.inner.state.lock().unwrap();
+        state.idle.values().map(Vec::len).sum()
+    }
+
+    /// Hands out a session matching `args`.
+    ///
+    /// Reuses an idle session with the same [SessionKey] if there is one, and starts a new session otherwise.
+    /// If the maximum number of sessions is reached, waits until a session is returned to the pool.
+    ///
+    /// Returns an `io::Error` if communication with the server failed or the session could not be started.
+    pub async fn checkout(&self, args: &SessionStartArgs) -> io::Result<SessionLease> {
+        let key = SessionKey::from(args);
+        loop {
+            let released = self.inner.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            match self.try_checkout(&key) {
+                Checkout::Reuse(mut pooled) => {
+                    pooled.uses += 1;
+                    log::trace!("Reusing session {}", pooled.session.id());
+                    return Ok(self.lease(key, pooled));
+                }
+                Checkout::Start(evicted) => {
+                    // Gives the slot back if starting fails or this future is dropped
+                    let slot = Slot::new(self);
+                    if let Some(evicted) = evicted {
+                        self.stop(evicted).await;
+                    }
+                    let session = self.start(args).await?;
+                    slot.transfer();
+                    return Ok(self.lease(key, PooledSession { session, uses: 1 }));
+                }
+                Checkout::Wait => released.await,
+            }
+        }
+    }
+
+    /// Stops all idle sessions.
+    pub async fn shutdown(&self) {
+        let idle = {
+            let mut state = self.inner.state.lock().unwrap();
+            let idle = state.idle.drain().flat_map(|(_, s)| s).collect::<Vec<_>>();
+            state.live -= idle.len();
+            idle
+        };
+        for pooled in idle {
+            self.stop(pooled).await;
+        }
+        self.inner.released.notify_waiters();
+    }
+
+    fn try_checkout(&self, key: &SessionKey) -> Checkout {
+        let mut state = self.inner.state.lock().unwrap();
+        if let Some(pooled) = state.idle.get_mut(key).and_then(Vec::pop) {
+            return Checkout::Reuse(pooled);
+        }
+        if state.live < self.inner.config.max_sessions {
+            state.live += 1;
+            return Checkout::Start(None);
+        }
+        // Make room by replacing an idle session of another key
+        let evicted = state.idle.values_mut().find_map(Vec::pop);
+        match evicted {
+            Some(evicted) => Checkout::Start(Some(evicted)),
+            None => Checkout::Wait,
+        }
+    }
+
+    async fn start(&self, args: &SessionStartArgs) -> io::Result<Session> {
+        let mut client = self.inner.client.clone();
+        let session = Session::start(&mut client, args).await?;
+        log::trace!("Started session {}", session.id());
+        Ok(session)
+    }
+
+    async fn stop(&self, pooled: PooledSession) {
+        let id = pooled.session.id().to_owned();
+        log::trace!("Stopping session {}", id);
+        if let Err(e) = pooled.session.stop().await {
+            log::warn!("Could not stop session {}: {}", id, e);
+        }
+    }
+
+    fn lease(&self, key: SessionKey, pooled: PooledSession) -> SessionLease {
+        SessionLease {
+            manager: self.clone(),
+            key,
+            pooled: Some(pooled),
+            failed: false,
+        }
+    }
+
+    /// Returns a session to the pool, or stops it if it must not be reused.
+    async fn release(&self, key: SessionKey, mut pooled: PooledSession, failed: bool) {
+        let max_uses = self.inner.config.max_uses;
+        let mut recycle = failed || (max_uses > 0 && pooled.uses >= max_uses);
+
+        if !recycle {
+            let args = PurgeTheoryArgs {
+                session_id: pooled.session.id().to_owned(),
+                all: Some(true),
+                ..Default::default()
+            };
+            match pooled.session.purge_theories_with(args).await {
+                Ok(SyncResult::Ok(_)) => {}
+                Ok(SyncResult::Error(_)) | Err(_) => {
+                    log::warn!("Could not purge session {}", pooled.session.id());
+                    recycle = true;
+                }
+            }
+        }
+
+        if recycle {
+            let _slot = Slot::new(self);
+            self.stop(pooled).await;
+        } else {
+            let mut state = self.inner.state.lock().unwrap();
+            state.idle.entry(key).or_default().push(pooled);
+            drop(state);
+            self.inner.released.notify_waiters();
+        }
+    }
+}
+
+/// A slot counted in the number of live sessions, given back when dropped unless it was transferred to a session.
+struct Slot<'a> {
+    manager: Option<&'a SessionManager>,
+}
+
+impl<'a> Slot<'a> {
+    fn new(manager: &'a SessionManager) -> Self {
+        Self {
+            manager: Some(manager),
+        }
+    }
+
+    /// Keeps the slot counted, as it is now held by a live session.
+    fn transfer(mut self) {
+        self.manager = None;
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        if let Some(manager) = self.manager {
+            manager.inner.state.lock().unwrap().live -= 1;
+            manager.inner.released.notify_waiters();
+        }
+    }
+}
+
+/// A session handed out by a [SessionManager].
+///
+/// Return it to the pool using [SessionLease::release].
+/// If the lease is dropped, the session is returned to the pool in the background.
+pub struct SessionLease {
+    manager: SessionManager,
+    key: SessionKey,
+    pooled: Option<PooledSession>,
+    failed: bool,
+}
+
+impl SessionLease {
+    /// Returns the leased session.
+    pub fn session(&mut self) -> &mut Session {
+        &mut self
+            .pooled
+            .as_mut()
+            .expect("Session already released")
+            .session
+    }
+
+    /// Adds the given theory files to the leased session.
+    /// If the command fails, the session is not reused after it has been released.
+    pub async fn use_theories(
+        &mut self,
+        theories: &[&str],
+    ) -> io::Result<AsyncResult<UseTheoryResults, ()>> {
+        let res = self.session().use_theories(theories).await;
+        if !matches!(res, Ok(AsyncResult::Finished(_))) {
+            self.mark_failed();
+        }
+        res
+    }
+
+    /// Marks the session as faulty, such that it is stopped instead of being reused.
+    pub fn mark_failed(&mut self) {
+        self.failed = true;
+    }
+
+    /// Purges all theories and returns the session to the pool.
+    pub async fn release(mut self) {
+        if let Some(pooled) = self.pooled.take() {
+            self.manager
+                .release(self.key.clone(), pooled, self.failed)
+                .await;
+        }
+    }
+}
+
+impl Drop for SessionLease {
+    fn drop(&mut self) {
+        if let Some(pooled) = self.pooled.take() {
+            let manager = self.manager.clone();
+            let key = self.key.clone();
+            let failed = self.failed;
+            match tokio::runtime::Handle::try_current() {
+                Ok(handle) => {
+                    handle.spawn(async move { manager.release(key, pooled, failed).await });
+                }
+                Err(_) => {
+                    // Without a runtime, the session is dropped and stopped on a best-effort basis
+                    manager.inner.state.lock().unwrap().live -= 1;
+                    manager.inner.released.notify_waiters();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::ServerBuilder;
+    use serial_test::serial;
+    use std::time::Duration;
+
+    #[test]
+    fn test_session_key() {
+        let args = SessionStartArgs::session("HOL").options(["threads=2"]);
+        assert_eq!(SessionKey::from(&args), SessionKey::from(&args.clone()));
+        let ascii = args.clone().print_mode(["ASCII"]);
+        assert_ne!(SessionKey::from(&args), SessionKey::from(&ascii));
+        let prefs = args.clone().preferences("threads=4");
+        assert_ne!(SessionKey::from(&args), SessionKey::from(&prefs));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_checkout_releases_slot() {
+        // A server that accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((con, _)) = listener.accept().await {
+                connections.push(con);
+            }
+        });
+        let manager = SessionManager::new(
+            IsabelleClient::connect(None, port, "password"),
+            SessionManagerConfig::default(),
+        );
+        let args = SessionStartArgs::session("HOL");
+        let checkout = manager.checkout(&args);
+        assert!(tokio::time::timeout(Duration::from_millis(100), checkout)
+            .await
+            .is_err());
+        assert_eq!(manager.live(), 0);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_reuse_warm_session() {
+        let mut server = ServerBuilder::new()
+            .name("test")
+            .unique_name(true)
+            .run()
+            .unwrap();
+        let manager = SessionManager::new(
+            IsabelleClient::for_server(&server),
+            SessionManagerConfig {
+                max_sessions: 1,
+                max_uses: 2,
+            },
+        );
+        let args = SessionStartArgs::session("HOL");
+
+        let mut lease = manager.checkout(&args).await.unwrap();
+        let id = lease.session().id().to_owned();
+        let res = lease
+            .use_theories(&["~~/src/HOL/Examples/Drinker"])
+            .await
+            .unwrap();
+        assert!(res.finished().ok);
+        lease.release().await;
+
+        // Second use reuses the warm session
+        let mut lease = manager.checkout(&args).await.unwrap();
+        assert_eq!(lease.session().id(), id);
+        lease.release().await;
+
+        // Third use gets a fresh session
+        let mut lease = manager.checkout(&args).await.unwrap();
+        assert_ne!(lease.session().id(), id);
+        lease.release().await;
+
+        manager.shutdown().await;
+        assert_eq!(manager.live(), 0);
+        server.exit().unwrap();
+    }
+}
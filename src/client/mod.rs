@@ -1,7 +1,13 @@
 /// Contains the arguments data types for the Isabelle server commands
 pub mod args;
+/// Contains a pool of warm sessions that are shared between users
+pub mod manager;
 /// Contains the result data types the Isabelle servers responses with
 pub mod results;
+/// Contains a job queue that schedules commands with priorities and per-tenant fairness
+pub mod scheduler;
+/// Contains the handle to sessions running on an Isabelle server
+pub mod session;
 
 use serde::Deserialize;
 use serde::Serialize;
@@ -9,14 +15,23 @@ use serde::Serialize;
 use crate::server::IsabelleServer;
 
 use std::fmt::Display;
-use std::io;
-use std::{
-    io::{BufRead, BufReader, BufWriter, Write},
-    net::TcpStream,
-};
+use std::future::Future;
+use std::io::{self, BufRead, Write};
+use std::net::ToSocketAddrs;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 
 use self::args::*;
 use self::results::*;
+use self::session::Session;
 
 /// A command to be sent to the Isabelle server.
 /// It consists of a `name` and optional arguments `args` which are serialized as JSON.
@@ -86,6 +101,15 @@ impl<T, F> AsyncResult<T, F> {
             AsyncResult::Error(_) => panic!("Called unwrap on Error result"),
         }
     }
+
+    /// Consumes the result and returns the result value if the task was successful, panics otherwise.
+    pub fn into_finished(self) -> T {
+        match self {
+            AsyncResult::Finished(t) => t,
+            AsyncResult::Failed(_) => panic!("Called unwrap on Failed result"),
+            AsyncResult::Error(_) => panic!("Called unwrap on Error result"),
+        }
+    }
 }
 
 /// Result of a failed asynchronous task.
@@ -102,7 +126,104 @@ pub struct FailedResult<T> {
     pub context: Option<T>,
 }
 
+type BoxedTaskFuture<'a, T, F> =
+    Pin<Box<dyn Future<Output = io::Result<AsyncResult<T, F>>> + Send + 'a>>;
+
+/// Future of an asynchronous command whose task runs on the server.
+///
+/// If the future is dropped before the task has finished, for example because it lost a `tokio::select!` race,
+/// a best-effort `cancel` is sent to the server for the task.
+/// Call [AsyncTask::detach] to let the task keep running on the server instead.
+///
+/// Notes the server emits while the task is running are logged, and can be received using [AsyncTask::notes].
+pub struct AsyncTask<'a, T, F> {
+    future: BoxedTaskFuture<'a, T, F>,
+    control: Arc<TaskControl>,
+}
+
+/// Settings of a running task that can be changed through its [AsyncTask].
+struct TaskControl {
+    cancel_on_drop: AtomicBool,
+    notes: Mutex<Option<mpsc::UnboundedSender<Note>>>,
+}
+
+impl<'a, T: 'a, F: 'a> AsyncTask<'a, T, F> {
+    /// Keeps the task running on the server if this future is dropped before the task has finished.
+    pub fn detach(self) -> Self {
+        self.control.cancel_on_drop.store(false, Ordering::SeqCst);
+        self
+    }
+
+    /// Forwards the notes emitted by the server while the task is running to `notes`.
+    pub fn notes(self, notes: mpsc::UnboundedSender<Note>) -> Self {
+        *self.control.notes.lock().unwrap() = Some(notes);
+        self
+    }
+
+    /// Maps the result value of a finished task using `f`.
+    fn map_finished<U>(self, f: impl FnOnce(T) -> U + Send + 'a) -> AsyncTask<'a, U, F> {
+        let future = self.future;
+        AsyncTask {
+            future: Box::pin(async move {
+                Ok(match future.await? {
+                    AsyncResult::Finished(t) => AsyncResult::Finished(f(t)),
+                    AsyncResult::Failed(failed) => AsyncResult::Failed(failed),
+                    AsyncResult::Error(e) => AsyncResult::Error(e),
+                })
+            }),
+            control: self.control,
+        }
+    }
+}
+
+impl<'a, T, F> Future for AsyncTask<'a, T, F> {
+    type Output = io::Result<AsyncResult<T, F>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Sends a best-effort `cancel` for a running task when dropped, unless it was disarmed or the task was detached.
+struct CancelGuard {
+    client: IsabelleClient,
+    task: Option<String>,
+    control: Arc<TaskControl>,
+}
+
+impl CancelGuard {
+    /// Marks the task as terminated, such that no `cancel` is sent on drop.
+    fn disarm(&mut self) {
+        self.task = None;
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        let task = match self.task.take() {
+            Some(task) if self.control.cancel_on_drop.load(Ordering::SeqCst) => task,
+            _ => return,
+        };
+        log::debug!("Task {} was abandoned, cancelling it", task);
+        let client = self.client.clone();
+        // Do not block the (possibly async) context the future is dropped in
+        std::thread::spawn(move || {
+            let cmd = Command {
+                name: "cancel".to_owned(),
+                args: Some(CancelArgs { task: task.clone() }),
+            };
+            if let Err(e) = client.dispatch_blocking(&cmd, Duration::from_secs(5)) {
+                log::warn!("Could not cancel task {}: {}", task, e);
+            }
+        });
+    }
+}
+
+type Reader = BufReader<OwnedReadHalf>;
+type Writer = BufWriter<OwnedWriteHalf>;
+
 /// Provides interaction with Isabelle servers.
+#[derive(Clone)]
 pub struct IsabelleClient {
     /// The address of the Isabelle server
     addr: String,
@@ -118,7 +239,7 @@ impl IsabelleClient {
     /// - `address`: specifies the server address. If it is `None`, "127.0.0.1" is use as a default
     /// - `port`: specifies the server port
     /// - `pass`: the password
-    pub fn connect(address: Option<&str>, port: u32, pass: &str) -> Self {
+    pub fn connect(address: Option<&str>, port: u16, pass: &str) -> Self {
         let addr = format!("{}:{}", address.unwrap_or("127.0.0.1"), port);
 
         Self {
@@ -134,19 +255,14 @@ impl IsabelleClient {
 
     /// Performs the initial password exchange(i.e. password exchange) between a new client client and server.
     /// Returns a `Result` indicating the success or failure of the handshake.
-    fn handshake(&self, stream: &TcpStream) -> io::Result<()> {
-        let mut writer = BufWriter::new(stream.try_clone().unwrap());
-        let mut reader = BufReader::new(stream.try_clone().unwrap());
-
-        writer.write_all(format!("{}\n", self.pass).as_bytes())?;
-        writer.flush()?;
-
-        if let Some(e) = stream.take_error()? {
-            return Err(e);
-        }
+    async fn handshake(&self, reader: &mut Reader, writer: &mut Writer) -> io::Result<()> {
+        writer
+            .write_all(format!("{}\n", self.pass).as_bytes())
+            .await?;
+        writer.flush().await?;
 
         let mut res = String::new();
-        reader.read_line(&mut res)?;
+        reader.read_line(&mut res).await?;
         log::trace!("Handshake result: {}", res.trim());
         if !res.starts_with("OK") {
             return Err(io::Error::new(
@@ -179,18 +295,35 @@ impl IsabelleClient {
     /// Creates a new connection to the server and performs the initial password exchange
     /// handshake. Returns a tuple of buffered reader and writer wrapped around the TcpStream
     /// connection.
-    fn new_connection(&self) -> io::Result<(BufReader<TcpStream>, BufWriter<TcpStream>)> {
-        let con = TcpStream::connect(&self.addr)?;
+    async fn new_connection(&self) -> io::Result<(Reader, Writer)> {
+        let con = TcpStream::connect(&self.addr).await?;
+        if let Some(e) = con.take_error()? {
+            return Err(e);
+        }
 
-        // Perform password exchange
-        self.handshake(&con)?;
+        let (read_half, write_half) = con.into_split();
+        let mut reader = BufReader::new(read_half);
+        let mut writer = BufWriter::new(write_half);
 
-        let writer = BufWriter::new(con.try_clone().unwrap());
-        let reader = BufReader::new(con.try_clone().unwrap());
+        // Perform password exchange
+        self.handshake(&mut reader, &mut writer).await?;
 
         Ok((reader, writer))
     }
 
+    /// Reads the next line sent by the server into `buf`.
+    /// Returns an `io::Error` if the server closed the connection.
+    async fn read_response(reader: &mut Reader, buf: &mut String) -> io::Result<()> {
+        buf.clear();
+        if reader.read_line(buf).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed by server",
+            ));
+        }
+        Ok(())
+    }
+
     /// Dispatches asynchronous [Command] `cmd` to start the task on the server.
     ///
     /// The method dispatches the `cmd` which starts an asynchronous task at the server.
@@ -198,7 +331,10 @@ impl IsabelleClient {
     /// as an `AsyncResult<R, F>` where `R` is the type of the response when the task is finished and
     /// `F` is the type of the response when the task fails.
     ///
-    /// Notes printed by the server are logged and cannot be accessed.
+    /// If the returned future is dropped while the task is running and `control` requests it,
+    /// a best-effort `cancel` is sent to the server.
+    ///
+    /// Notes printed by the server are logged, and forwarded to the notes channel of `control` if there is one.
     ///
     /// Returns an `io::Error` if communication with the server failed.
     async fn dispatch_async<
@@ -208,35 +344,51 @@ impl IsabelleClient {
     >(
         &self,
         cmd: &Command<T>,
-        reader: &mut BufReader<TcpStream>,
-        writer: &mut BufWriter<TcpStream>,
+        reader: &mut Reader,
+        writer: &mut Writer,
+        control: Arc<TaskControl>,
     ) -> Result<AsyncResult<R, F>, io::Error> {
         // Dispatch the command as sync to start the task. Return Error if it failed
-        if let SyncResult::Error(e) = self
+        let task = match self
             .dispatch_sync::<T, Task, Message>(cmd, reader, writer)
             .await?
         {
+            SyncResult::Ok(task) => task,
             // Cast to async result
-            return Ok(AsyncResult::Error(e));
+            SyncResult::Error(e) => return Ok(AsyncResult::Error(e)),
+        };
+
+        let mut guard = CancelGuard {
+            client: self.clone(),
+            task: Some(task.task),
+            control: control.clone(),
         };
 
         // Wait for the task to finish or fail, and collect notes along the way
         let mut res = String::new();
         loop {
-            res.clear();
-            reader.read_line(&mut res)?;
+            Self::read_response(reader, &mut res).await?;
             let res = res.trim();
             if let Some(finish_response) = res.strip_prefix("FINISHED") {
                 // If the task has finished, parse the response
+                guard.disarm();
                 let parsed = self.parse_response(finish_response.trim())?;
                 return Ok(AsyncResult::Finished(parsed));
             } else if let Some(failed_response) = res.strip_prefix("FAILED") {
                 // If the task has failed, parse the response
+                guard.disarm();
                 let parsed = self.parse_response(failed_response.trim())?;
                 return Ok(AsyncResult::Failed(parsed));
             } else if let Some(note) = res.strip_prefix("NOTE") {
-                // If it's a note, log it and continue the loop
+                // If it's a note, log and forward it, then continue the loop
                 log::trace!("{}", note);
+                if let Some(notes) = control.notes.lock().unwrap().as_ref() {
+                    match self.parse_response(note.trim()) {
+                        // The receiver may have been dropped, the note is discarded in that case
+                        Ok(note) => _ = notes.send(note),
+                        Err(e) => log::warn!("Could not parse note: {}", e),
+                    }
+                }
             } else {
                 // Occasionally the server omits some seemingly random numeric logs.
                 // Log and discard them, then continue the loop.
@@ -258,14 +410,14 @@ impl IsabelleClient {
     >(
         &self,
         cmd: &Command<T>,
-        reader: &mut BufReader<TcpStream>,
-        writer: &mut BufWriter<TcpStream>,
+        reader: &mut Reader,
+        writer: &mut Writer,
     ) -> Result<SyncResult<R, E>, io::Error> {
-        writer.write_all(&cmd.as_bytes())?;
-        writer.flush()?;
+        writer.write_all(&cmd.as_bytes()).await?;
+        writer.flush().await?;
+        let mut res = String::new();
         loop {
-            let mut res = String::new();
-            reader.read_line(&mut res)?;
+            Self::read_response(reader, &mut res).await?;
             let res = res.trim();
             if let Some(response_ok) = res.strip_prefix("OK") {
                 let res = self.parse_response(response_ok.trim())?;
@@ -281,13 +433,115 @@ impl IsabelleClient {
         }
     }
 
+    /// Dispatches synchronous [Command] `cmd` over a new blocking connection and returns the raw response line.
+    ///
+    /// This is meant for contexts in which no async runtime can be used, e.g., `Drop` implementations.
+    /// Connecting, writing, and reading each time out after `timeout`.
+    fn dispatch_blocking<T: Serialize>(
+        &self,
+        cmd: &Command<T>,
+        timeout: Duration,
+    ) -> io::Result<String> {
+        let addr = self.addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid address {}", self.addr),
+            )
+        })?;
+        let con = std::net::TcpStream::connect_timeout(&addr, timeout)?;
+        con.set_read_timeout(Some(timeout))?;
+        con.set_write_timeout(Some(timeout))?;
+
+        let mut writer = std::io::BufWriter::new(con.try_clone()?);
+        let mut reader = std::io::BufReader::new(con);
+
+        writer.write_all(format!("{}\n", self.pass).as_bytes())?;
+        writer.flush()?;
+        let mut res = String::new();
+        reader.read_line(&mut res)?;
+        if !res.starts_with("OK") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Handshake failed",
+            ));
+        }
+
+        writer.write_all(&cmd.as_bytes())?;
+        writer.flush()?;
+        loop {
+            res.clear();
+            if reader.read_line(&mut res)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed by server",
+                ));
+            }
+            if res.starts_with("OK") || res.starts_with("ERROR") {
+                return Ok(res.trim().to_owned());
+            }
+            log::trace!("Unknown message format: {}", res.trim());
+        }
+    }
+
+    /// Sends `shutdown` to the server over a blocking connection, see [IsabelleClient::dispatch_blocking].
+    pub(crate) fn shutdown_blocking(&self, timeout: Duration) -> io::Result<()> {
+        let cmd: Command<()> = Command {
+            name: "shutdown".to_owned(),
+            args: None,
+        };
+        let res = self.dispatch_blocking(&cmd, timeout)?;
+        if res.starts_with("OK") {
+            Ok(())
+        } else {
+            Err(io::Error::other(res))
+        }
+    }
+
+    /// Sends `echo` to the server over a blocking connection, see [IsabelleClient::dispatch_blocking].
+    pub(crate) fn echo_blocking(&self, timeout: Duration) -> io::Result<()> {
+        let cmd = Command {
+            name: "echo".to_owned(),
+            args: Some("ping"),
+        };
+        let res = self.dispatch_blocking(&cmd, timeout)?;
+        if res.starts_with("OK") {
+            Ok(())
+        } else {
+            Err(io::Error::other(res))
+        }
+    }
+
+    /// Starts the asynchronous [Command] `cmd` on a new connection.
+    fn start_task<'a, T, R, F>(&self, cmd: Command<T>) -> AsyncTask<'a, R, F>
+    where
+        T: Serialize + Send + Sync + 'a,
+        R: serde::de::DeserializeOwned + Send + 'a,
+        F: serde::de::DeserializeOwned + Send + 'a,
+    {
+        let client = self.clone();
+        let control = Arc::new(TaskControl {
+            cancel_on_drop: AtomicBool::new(true),
+            notes: Mutex::new(None),
+        });
+        let task_control = control.clone();
+        AsyncTask {
+            future: Box::pin(async move {
+                let (mut reader, mut writer) = client.new_connection().await?;
+                client
+                    .dispatch_async(&cmd, &mut reader, &mut writer, task_control)
+                    .await
+            }),
+            control,
+        }
+    }
+
     /// Identity function: Returns its argument as result
     pub async fn echo(&mut self, echo: &str) -> Result<SyncResult<String, String>, io::Error> {
         let cmd = Command {
             name: "echo".to_owned(),
             args: Some(echo.to_owned()),
         };
-        let (mut reader, mut writer) = self.new_connection()?;
+        let (mut reader, mut writer) = self.new_connection().await?;
         self.dispatch_sync(&cmd, &mut reader, &mut writer).await
     }
 
@@ -298,7 +552,7 @@ impl IsabelleClient {
             name: "shutdown".to_owned(),
             args: None,
         };
-        let (mut reader, mut writer) = self.new_connection()?;
+        let (mut reader, mut writer) = self.new_connection().await?;
         self.dispatch_sync(&cmd, &mut reader, &mut writer).await
     }
 
@@ -309,64 +563,57 @@ impl IsabelleClient {
             name: "cancel".to_owned(),
             args: Some(CancelArgs { task: task_id }),
         };
-        let (mut reader, mut writer) = self.new_connection()?;
+        let (mut reader, mut writer) = self.new_connection().await?;
         self.dispatch_sync(&cmd, &mut reader, &mut writer).await
     }
 
     /// Prepares a session image for interactive use of theories.
-    pub async fn session_build(
+    /// With `verbose` set, the build output is delivered as notes, see [AsyncTask::notes].
+    pub fn session_build<'a>(
         &mut self,
-        args: &SessionBuildArgs,
-    ) -> Result<AsyncResult<SessionBuildResults, SessionBuildResults>, io::Error> {
+        args: &'a SessionBuildArgs,
+    ) -> AsyncTask<'a, SessionBuildResults, SessionBuildResults> {
         let cmd = Command {
             name: "session_build".to_owned(),
             args: Some(args),
         };
-        let (mut reader, mut writer) = self.new_connection()?;
-        self.dispatch_async(&cmd, &mut reader, &mut writer).await
+        self.start_task(cmd)
     }
 
     /// Starts a new Isabelle/PIDE session with underlying Isabelle/ML process, based on a session image that it produces on demand using `session_build`.
-    /// Returns the `session_id`, which provides the internal identification of the session object within the server process.
-    pub async fn session_start(
-        &mut self,
-        args: &SessionBuildArgs,
-    ) -> Result<AsyncResult<SessionStartResult, ()>, io::Error> {
+    /// Returns a [Session] bound to this server, which provides the internal identification of the session object within the server process.
+    pub fn session_start<'a>(&mut self, args: &'a SessionStartArgs) -> AsyncTask<'a, Session, ()> {
         let cmd = Command {
             name: "session_start".to_owned(),
             args: Some(args),
         };
-
-        let (mut reader, mut writer) = self.new_connection()?;
-        self.dispatch_async(&cmd, &mut reader, &mut writer).await
+        let client = self.clone();
+        self.start_task::<_, SessionStartResult, ()>(cmd)
+            .map_finished(move |res| Session::new(client, res))
     }
 
     /// Forces a shutdown of the identified session.
-    pub async fn session_stop(
+    pub fn session_stop<'a>(
         &mut self,
-        args: &SessionStopArgs,
-    ) -> Result<AsyncResult<SessionStopResult, SessionStopResult>, io::Error> {
+        args: &'a SessionStopArgs,
+    ) -> AsyncTask<'a, SessionStopResult, SessionStopResult> {
         let cmd = Command {
             name: "session_stop".to_owned(),
             args: Some(args),
         };
-
-        let (mut reader, mut writer) = self.new_connection()?;
-        self.dispatch_async(&cmd, &mut reader, &mut writer).await
+        self.start_task(cmd)
     }
 
     /// Updates the identified session by adding the current version of theory files to it, while dependencies are resolved implicitly.
-    pub async fn use_theories(
+    pub fn use_theories<'a>(
         &mut self,
-        args: &UseTheoriesArgs,
-    ) -> Result<AsyncResult<UseTheoryResults, ()>, io::Error> {
+        args: &'a UseTheoriesArgs,
+    ) -> AsyncTask<'a, UseTheoryResults, ()> {
         let cmd = Command {
             name: "use_theories".to_owned(),
             args: Some(args),
         };
-
-        let (mut reader, mut writer) = self.new_connection()?;
-        self.dispatch_async(&cmd, &mut reader, &mut writer).await
+        self.start_task(cmd)
     }
 
     /// Updates the identified session by removing theories.
@@ -380,7 +627,7 @@ impl IsabelleClient {
             args: Some(args),
         };
 
-        let (mut reader, mut writer) = self.new_connection()?;
+        let (mut reader, mut writer) = self.new_connection().await?;
         self.dispatch_sync(&cmd, &mut reader, &mut writer).await
     }
 }
@@ -389,24 +636,26 @@ impl IsabelleClient {
 mod test {
 
     use super::*;
-    use crate::server::run_server;
+    use crate::server::ServerBuilder;
     use serial_test::serial;
 
     struct TestContext {
-        server: IsabelleServer,
+        /// Shuts down the server when the context is dropped
+        _server: IsabelleServer,
         client: IsabelleClient,
     }
 
-    impl Drop for TestContext {
-        fn drop(&mut self) {
-            self.server.exit().unwrap();
-        }
-    }
-
     fn setup_test() -> TestContext {
-        let server = run_server(Some("test")).unwrap();
+        let server = ServerBuilder::new()
+            .name("test")
+            .unique_name(true)
+            .run()
+            .unwrap();
         let client = IsabelleClient::for_server(&server);
-        TestContext { server, client }
+        TestContext {
+            _server: server,
+            client,
+        }
     }
 
     #[tokio::test]
@@ -451,6 +700,19 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_session_build_verbose_notes() {
+        let client = &mut setup_test().client;
+
+        let arg = SessionBuildArgs::session("HOL").verbose(true);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let res = client.session_build(&arg).notes(tx).await.unwrap();
+        assert!(res.finished().ok);
+        assert!(matches!(rx.recv().await, Some(Note::Message(_))));
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_session_build_unknown() {
@@ -467,7 +729,7 @@ mod test {
     async fn test_session_start_hol() {
         let client = &mut setup_test().client;
 
-        let arg = SessionBuildArgs::session("HOL");
+        let arg = SessionStartArgs::session("HOL");
 
         let res = client.session_start(&arg).await.unwrap();
         assert!(matches!(res, AsyncResult::Finished(_)));
@@ -477,7 +739,7 @@ mod test {
     #[serial]
     async fn test_session_start_unknown() {
         let client = &mut setup_test().client;
-        let arg = SessionBuildArgs::session("unknown_sessions");
+        let arg = SessionStartArgs::session("unknown_sessions");
 
         let res = client.session_start(&arg).await.unwrap();
 
@@ -489,13 +751,10 @@ mod test {
     async fn test_session_stop_active() {
         let client = &mut setup_test().client;
 
-        let arg = SessionBuildArgs::session("HOL");
+        let arg = SessionStartArgs::session("HOL");
         let res = client.session_start(&arg).await.unwrap();
-        if let AsyncResult::Finished(res) = res {
-            let arg = SessionStopArgs {
-                session_id: res.session_id,
-            };
-            if let AsyncResult::Finished(stop_res) = client.session_stop(&arg).await.unwrap() {
+        if let AsyncResult::Finished(session) = res {
+            if let AsyncResult::Finished(stop_res) = session.stop().await.unwrap() {
                 assert!(stop_res.ok);
             } else {
                 unreachable!();
@@ -534,11 +793,10 @@ mod test {
     async fn use_theory_in_hol() {
         let client = &mut setup_test().client;
 
-        let arg = SessionBuildArgs::session("HOL");
+        let arg = SessionStartArgs::session("HOL");
         let res = client.session_start(&arg).await.unwrap();
         if let AsyncResult::Finished(res) = res {
-            let arg =
-                UseTheoriesArgs::for_session(&res.session_id, &["~~/src/HOL/Examples/Drinker"]);
+            let arg = UseTheoriesArgs::for_session(res.id(), &["~~/src/HOL/Examples/Drinker"]);
 
             match client.use_theories(&arg).await.unwrap() {
                 AsyncResult::Error(e) => unreachable!("{:?}", e),
@@ -555,10 +813,10 @@ mod test {
     async fn use_theory_unknown() {
         let client = &mut setup_test().client;
 
-        let arg = SessionBuildArgs::session("HOL");
+        let arg = SessionStartArgs::session("HOL");
         let res = client.session_start(&arg).await.unwrap();
         if let AsyncResult::Finished(res) = res {
-            let arg = UseTheoriesArgs::for_session(&res.session_id, &["~~/src/HOL/foo"]);
+            let arg = UseTheoriesArgs::for_session(res.id(), &["~~/src/HOL/foo"]);
             let got = client.use_theories(&arg).await.unwrap();
 
             assert!(matches!(got, AsyncResult::Failed(_)));
@@ -566,4 +824,66 @@ mod test {
             unreachable!()
         }
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn session_use_and_purge_theories() {
+        let client = &mut setup_test().client;
+
+        let arg = SessionStartArgs::session("HOL");
+        let mut session = client.session_start(&arg).await.unwrap().into_finished();
+
+        let got = session
+            .use_theories(&["~~/src/HOL/Examples/Drinker"])
+            .await
+            .unwrap();
+        assert!(got.finished().ok);
+
+        let got = session
+            .purge_theories(&["~~/src/HOL/Examples/Drinker"])
+            .await
+            .unwrap();
+        assert!(matches!(got, SyncResult::Ok(_)));
+
+        assert!(session.stop().await.unwrap().finished().ok);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_task_is_cancelled() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut client = IsabelleClient::connect(None, port, "pass");
+
+        let fake_server = tokio::spawn(async move {
+            let mut lines = vec![];
+            for _ in 0..2 {
+                let (con, _) = listener.accept().await.unwrap();
+                let (r, mut w) = con.into_split();
+                let mut r = BufReader::new(r);
+                let mut line = String::new();
+                // Handshake
+                r.read_line(&mut line).await.unwrap();
+                w.write_all(b"OK\n").await.unwrap();
+                line.clear();
+                r.read_line(&mut line).await.unwrap();
+                lines.push(line.trim().to_owned());
+                // Start the task but never finish it
+                w.write_all(b"OK {\"task\":\"task-1\"}\n").await.unwrap();
+                std::mem::forget((r, w));
+            }
+            lines
+        });
+
+        let args = UseTheoriesArgs::for_session("session", &["Foo"]);
+        tokio::select! {
+            _ = client.use_theories(&args) => unreachable!(),
+            _ = tokio::time::sleep(std::time::Duration::from_millis(200)) => {}
+        }
+
+        let lines = fake_server.await.unwrap();
+        assert!(lines[0].starts_with("use_theories"));
+        assert_eq!(lines[1], r#"cancel {"task":"task-1"}"#);
+    }
 }
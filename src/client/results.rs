@@ -2,42 +2,81 @@
 use serde::{Deserialize, Serialize};
 
 /// Describes a source position within Isabelle text
-#[derive(Deserialize, Serialize, Debug)]
-struct Position {
-    line: Option<usize>,
-    offset: Option<usize>,
-    end_offset: Option<usize>,
-    filed: Option<String>,
-    id: Option<usize>,
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Position {
+    pub line: Option<usize>,
+    pub offset: Option<usize>,
+    pub end_offset: Option<usize>,
+    pub file: Option<String>,
+    pub id: Option<usize>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+impl Position {
+    /// Returns the name of the theory the position refers to, derived from the file name, e.g., `Foo` for `~/Foo.thy`.
+    pub fn theory(&self) -> Option<&str> {
+        let file = self.file.as_deref()?;
+        let name = file.rsplit('/').next()?;
+        name.strip_suffix(".thy")
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct Message {
     /// The main message kinds are writeln (for regular output), warning, error.
-    kind: String,
-    message: String,
-    pos: Option<Position>,
+    pub kind: String,
+    pub message: String,
+    pub pos: Option<Position>,
+}
+
+impl Message {
+    /// Returns whether this is an error message.
+    pub fn is_error(&self) -> bool {
+        self.kind == "error"
+    }
+
+    /// Returns whether this is a warning.
+    pub fn is_warning(&self) -> bool {
+        self.kind == "warning"
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct TheoryProgress {
     /// = "writeln"
-    kind: String,
-    message: String,
-    session: String,
-    percentage: Option<usize>,
+    pub kind: String,
+    pub message: String,
+    /// The theory being processed
+    pub theory: String,
+    /// The session the theory belongs to
+    pub session: String,
+    pub percentage: Option<usize>,
 }
 
+/// Notes emitted by the server while an asynchronous task is running.
 #[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+pub enum Note {
+    /// Progress of loading a theory
+    TheoryProgress(TheoryProgress),
+    /// Regular output of the task, e.g., the verbose output of `session_build` naming the sessions being built
+    Message(Message),
+    /// Status of the theory nodes during `use_theories`
+    NodesStatus(NodesStatus),
+    /// Any other note
+    Other(serde_json::Value),
+}
+
+/// Timing in seconds
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
 pub struct Timing {
-    elapsed: f64,
-    cpu: f64,
-    gc: f64,
+    pub elapsed: f64,
+    pub cpu: f64,
+    pub gc: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Task {
-    task: String,
+    pub task: String,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -81,9 +120,9 @@ pub struct SessionBuildResult {
     /// Is zero if `ok` is true. Non-zero return code indicates and error.
     pub return_code: usize,
     /// If true, the build process was aborted after running too long
-    timeout: bool,
+    pub timeout: bool,
     /// Overall timing
-    timing: Timing,
+    pub timing: Timing,
 }
 
 /// Results for `session_build` command
@@ -92,7 +131,7 @@ pub struct SessionBuildResults {
     /// All sessions ok
     pub ok: bool,
     /// Is zero if `ok` is true. Non-zero return code indicates and error.
-    return_code: usize,
+    pub return_code: usize,
     /// The result of each build sessions
     pub sessions: Vec<SessionBuildResult>,
 }
@@ -150,3 +189,38 @@ pub struct PurgeTheoryResults {
     pub purged: Vec<PurgedTheory>,
     pub retained: Vec<PurgedTheory>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_notes() {
+        let note: Note = serde_json::from_str(
+            r#"{"task":"t","kind":"writeln","message":"Building HOL-Library ..."}"#,
+        )
+        .unwrap();
+        assert!(matches!(note, Note::Message(m) if m.message == "Building HOL-Library ..."));
+
+        let note: Note = serde_json::from_str(
+            r#"{"task":"t","kind":"writeln","message":"theory Foo 50%","theory":"Draft.Foo","session":"HOL","percentage":50}"#,
+        )
+        .unwrap();
+        assert!(matches!(note, Note::TheoryProgress(p) if p.percentage == Some(50)));
+
+        let note: Note = serde_json::from_str(r#"{"task":"t","foo":1}"#).unwrap();
+        assert!(matches!(note, Note::Other(_)));
+    }
+
+    #[test]
+    fn test_parse_message_position() {
+        let msg: Message = serde_json::from_str(
+            r#"{"kind":"error","message":"Failed","pos":{"line":5,"offset":60,"end_offset":62,"file":"/tmp/Foo.thy","id":12}}"#,
+        )
+        .unwrap();
+        assert!(msg.is_error());
+        let pos = msg.pos.unwrap();
+        assert_eq!(pos.line, Some(5));
+        assert_eq!(pos.theory(), Some("Foo"));
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{runtime::Handle, sync::oneshot};
+
+use super::{args::*, results::*, AsyncResult, IsabelleClient};
+
+/// Identifies a job submitted to a [Scheduler].
+pub type JobId = u64;
+
+type BoxedJob = Box<dyn FnOnce(IsabelleClient) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// Timing information of a single job, shared between the scheduler and the job's handle.
+struct JobInfo {
+    enqueued_at: Instant,
+    dispatched_at: Mutex<Option<Instant>>,
+}
+
+/// A job waiting in the queue.
+struct QueuedJob {
+    id: JobId,
+    tenant: String,
+    priority: i32,
+    info: Arc<JobInfo>,
+    run: BoxedJob,
+}
+
+/// Mutable state of the scheduler.
+#[derive(Default)]
+struct State {
+    queue: Vec<QueuedJob>,
+    running: usize,
+    next_id: JobId,
+    /// Monotonic counter, incremented on every dispatch.
+    tick: u64,
+    /// The tick at which a tenant has last been served.
+    last_served: HashMap<String, u64>,
+}
+
+impl State {
+    /// Returns the index of the job to dispatch next, given the tick at which each tenant was last served.
+    ///
+    /// Jobs with higher priority are always dispatched first.
+    /// Among jobs of equal priority, the tenant that has been waiting longest since it was last served is preferred.
+    /// Jobs of the same tenant are dispatched in submission order.
+    fn select(jobs: &[&QueuedJob], last_served: &HashMap<String, u64>) -> Option<usize> {
+        jobs.iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| {
+                let served_a = last_served.get(&a.tenant).copied().unwrap_or(0);
+                let served_b = last_served.get(&b.tenant).copied().unwrap_or(0);
+                a.priority
+                    .cmp(&b.priority)
+                    .then(served_b.cmp(&served_a))
+                    .then(b.id.cmp(&a.id))
+            })
+            .map(|(i, _)| i)
+    }
+
+    /// Removes and returns the next job to dispatch.
+    fn pop(&mut self) -> Option<QueuedJob> {
+        let jobs = self.queue.iter().collect::<Vec<_>>();
+        let idx = Self::select(&jobs, &self.last_served)?;
+        let job = self.queue.remove(idx);
+        self.tick += 1;
+        self.last_served.insert(job.tenant.clone(), self.tick);
+        Some(job)
+    }
+
+    /// Returns the ids of all queued jobs in the order in which they would be dispatched.
+    fn dispatch_order(&self) -> Vec<JobId> {
+        let mut remaining = self.queue.iter().collect::<Vec<_>>();
+        let mut last_served = self.last_served.clone();
+        let mut tick = self.tick;
+        let mut order = Vec::with_capacity(remaining.len());
+        while let Some(idx) = Self::select(&remaining, &last_served) {
+            let job = remaining.remove(idx);
+            tick += 1;
+            last_served.insert(job.tenant.clone(), tick);
+            order.push(job.id);
+        }
+        order
+    }
+}
+
+struct Inner {
+    client: IsabelleClient,
+    max_concurrent: usize,
+    /// Runtime the dispatched jobs are spawned on
+    runtime: Handle,
+    state: Mutex<State>,
+}
+
+/// Queues `use_theories` and `session_build` requests for a shared Isabelle server.
+///
+/// Jobs are dispatched by priority (higher first) and, among jobs of equal priority, fairly across tenants.
+/// At most `max_concurrent` jobs are running on the server at the same time.
+/// Jobs that have not yet been dispatched can be cancelled without ever reaching the server.
+///
+/// The scheduler spawns the dispatched jobs on the tokio runtime it was created in,
+/// so jobs can be submitted from any thread.
+#[derive(Clone)]
+pub struct Scheduler {
+    inner: Arc<Inner>,
+}
+
+impl Scheduler {
+    /// Creates a new scheduler dispatching jobs with `client`, running at most `max_concurrent` jobs at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime, use [Scheduler::with_runtime] in that case.
+    pub fn new(client: IsabelleClient, max_concurrent: usize) -> Self {
+        Self::with_runtime(client, max_concurrent, Handle::current())
+    }
+
+    /// Creates a new scheduler like [Scheduler::new] that spawns the dispatched jobs on `runtime`.
+    pub fn with_runtime(client: IsabelleClient, max_concurrent: usize, runtime: Handle) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                client,
+                max_concurrent: max_concurrent.max(1),
+                runtime,
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    /// Queues a `use_theories` command on behalf of `tenant`.
+    pub fn use_theories(
+        &self,
+        tenant: &str,
+        priority: i32,
+        args: UseTheoriesArgs,
+    ) -> JobHandle<AsyncResult<UseTheoryResults, ()>> {
+        self.submit(tenant, priority, move |mut client| async move {
+            client.use_theories(&args).await
+        })
+    }
+
+    /// Queues a `session_build` command on behalf of `tenant`.
+    pub fn session_build(
+        &self,
+        tenant: &str,
+        priority: i32,
+        args: SessionBuildArgs,
+    ) -> JobHandle<AsyncResult<SessionBuildResults, SessionBuildResults>> {
+        self.submit(tenant, priority, move |mut client| async move {
+            client.session_build(&args).await
+        })
+    }
+
+    /// Returns the number of jobs waiting to be dispatched.
+    pub fn queued(&self) -> usize {
+        self.inner.state.lock().unwrap().queue.len()
+    }
+
+    /// Returns the number of jobs currently running on the server.
+    pub fn running(&self) -> usize {
+        self.inner.state.lock().unwrap().running
+    }
+
+    fn submit<T, F, Fut>(&self, tenant: &str, priority: i32, f: F) -> JobHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(IsabelleClient) -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<T>> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let run: BoxedJob = Box::new(move |client| {
+            Box::pin(async move {
+                // The receiver may have been dropped, the result is discarded in that case
+                let _ = tx.send(f(client).await);
+            })
+        });
+        let info = Arc::new(JobInfo {
+            enqueued_at: Instant::now(),
+            dispatched_at: Mutex::new(None),
+        });
+
+        let id = {
+            let mut state = self.inner.state.lock().unwrap();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.queue.push(QueuedJob {
+                id,
+                tenant: tenant.to_owned(),
+                priority,
+                info: info.clone(),
+                run,
+            });
+            id
+        };
+        log::trace!("Queued job {} of tenant {}", id, tenant);
+
+        self.pump();
+
+        JobHandle {
+            id,
+            info,
+            scheduler: self.clone(),
+            rx,
+        }
+    }
+
+    /// Dispatches queued jobs until the concurrency limit is reached or the queue is empty.
+    fn pump(&self) {
+        loop {
+            let job = {
+                let mut state = self.inner.state.lock().unwrap();
+                if state.running >= self.inner.max_concurrent {
+                    return;
+                }
+                match state.pop() {
+                    Some(job) => {
+                        state.running += 1;
+                        job
+                    }
+                    None => return,
+                }
+            };
+
+            *job.info.dispatched_at.lock().unwrap() = Some(Instant::now());
+            log::trace!("Dispatching job {} of tenant {}", job.id, job.tenant);
+
+            let slot = RunningSlot(self.clone());
+            let client = self.inner.client.clone();
+            self.inner.runtime.spawn(async move {
+                let _slot = slot;
+                (job.run)(client).await;
+            });
+        }
+    }
+
+    fn position(&self, id: JobId) -> Option<usize> {
+        let state = self.inner.state.lock().unwrap();
+        state.dispatch_order().iter().position(|j| *j == id)
+    }
+
+    fn cancel(&self, id: JobId) -> bool {
+        let mut state = self.inner.state.lock().unwrap();
+        match state.queue.iter().position(|j| j.id == id) {
+            Some(idx) => {
+                // Dropping the job drops the result sender, which wakes up the handle
+                state.queue.remove(idx);
+                log::trace!("Cancelled job {}", id);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Counts a dispatched job as running until it is dropped,
+/// even if the job panics or its task is cancelled.
+struct RunningSlot(Scheduler);
+
+impl Drop for RunningSlot {
+    fn drop(&mut self) {
+        self.0.inner.state.lock().unwrap().running -= 1;
+        self.0.pump();
+    }
+}
+
+/// Handle to a job submitted to a [Scheduler].
+pub struct JobHandle<T> {
+    id: JobId,
+    info: Arc<JobInfo>,
+    scheduler: Scheduler,
+    rx: oneshot::Receiver<io::Result<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Returns the id of the job.
+    pub fn id(&self) -> JobId {
+        self.id
+    }
+
+    /// Returns the number of jobs that will be dispatched before this one,
+    /// or `None` if the job is no longer queued because it has been dispatched or cancelled.
+    pub fn position(&self) -> Option<usize> {
+        self.scheduler.position(self.id)
+    }
+
+    /// Returns the time the job has spent in the queue.
+    /// Once the job has been dispatched, this is the time between submission and dispatch.
+    pub fn wait_time(&self) -> Duration {
+        match *self.info.dispatched_at.lock().unwrap() {
+            Some(dispatched) => dispatched - self.info.enqueued_at,
+            None => self.info.enqueued_at.elapsed(),
+        }
+    }
+
+    /// Returns true if the job has been dispatched to the server.
+    pub fn dispatched(&self) -> bool {
+        self.info.dispatched_at.lock().unwrap().is_some()
+    }
+
+    /// Removes the job from the queue before it reaches the server.
+    /// Returns false if the job has already been dispatched.
+    pub fn cancel(&self) -> bool {
+        self.scheduler.cancel(self.id)
+    }
+
+    /// Waits for the job to finish and returns its result.
+    ///
+    /// Returns an `io::Error` of kind `Interrupted` if the job was cancelled before it was dispatched.
+    pub async fn result(self) -> io::Result<T> {
+        match self.rx.await {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                format!("Job {} was cancelled", self.id),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn job(state: &mut State, tenant: &str, priority: i32) -> JobId {
+        let id = state.next_id;
+        state.next_id += 1;
+        state.queue.push(QueuedJob {
+            id,
+            tenant: tenant.to_owned(),
+            priority,
+            info: Arc::new(JobInfo {
+                enqueued_at: Instant::now(),
+                dispatched_at: Mutex::new(None),
+            }),
+            run: Box::new(|_| Box::pin(async {})),
+        });
+        id
+    }
+
+    #[tokio::test]
+    async fn test_panicking_job_releases_slot() {
+        let client = IsabelleClient::connect(None, 4711, "password");
+        let scheduler = Scheduler::new(client, 1);
+        let panicking = scheduler.submit::<(), _, _>("a", 0, |_| async { panic!("job failed") });
+        let next = scheduler.submit("a", 0, |_| async { Ok(42) });
+        assert!(panicking.result().await.is_err());
+        assert_eq!(next.result().await.unwrap(), 42);
+    }
+
+    #[test]
+    fn test_dispatch_by_priority() {
+        let mut state = State::default();
+        let low = job(&mut state, "a", 0);
+        let high = job(&mut state, "a", 10);
+        let mid = job(&mut state, "b", 5);
+
+        assert_eq!(state.dispatch_order(), vec![high, mid, low]);
+    }
+
+    #[test]
+    fn test_dispatch_fair_across_tenants() {
+        let mut state = State::default();
+        let a1 = job(&mut state, "a", 0);
+        let a2 = job(&mut state, "a", 0);
+        let a3 = job(&mut state, "a", 0);
+        let b1 = job(&mut state, "b", 0);
+        let b2 = job(&mut state, "b", 0);
+
+        assert_eq!(state.dispatch_order(), vec![a1, b1, a2, b2, a3]);
+
+        // Popping must follow the predicted order
+        let popped = std::iter::from_fn(|| state.pop().map(|j| j.id)).collect::<Vec<_>>();
+        assert_eq!(popped, vec![a1, b1, a2, b2, a3]);
+    }
+
+    #[test]
+    fn test_dispatch_prefers_least_recently_served() {
+        let mut state = State::default();
+        let a1 = job(&mut state, "a", 0);
+        assert_eq!(state.pop().map(|j| j.id), Some(a1));
+
+        let a2 = job(&mut state, "a", 0);
+        let b1 = job(&mut state, "b", 0);
+        assert_eq!(state.dispatch_order(), vec![b1, a2]);
+    }
+}
@@ -0,0 +1,155 @@
+use std::{fmt, io, time::Duration};
+
+use super::{args::*, results::*, AsyncResult, AsyncTask, Command, IsabelleClient, SyncResult};
+
+/// A session running on an Isabelle server, as returned by [IsabelleClient::session_start].
+///
+/// The session is bound to the server it was started on and provides the commands that operate on it.
+/// Stop it using [Session::stop].
+/// If a session is dropped without being stopped, a warning is logged and a best-effort `session_stop` is sent to the server.
+pub struct Session {
+    client: IsabelleClient,
+    id: String,
+    task: String,
+    tmp_dir: Option<String>,
+    stopped: bool,
+}
+
+impl Session {
+    pub(super) fn new(client: IsabelleClient, res: SessionStartResult) -> Self {
+        Self {
+            client,
+            id: res.session_id,
+            task: res.task,
+            tmp_dir: res.tmp_dir,
+            stopped: false,
+        }
+    }
+
+    /// Starts a session with `args`, treating a failed start as an error.
+    pub(crate) async fn start(
+        client: &mut IsabelleClient,
+        args: &SessionStartArgs,
+    ) -> io::Result<Session> {
+        match client.session_start(args).await? {
+            AsyncResult::Finished(session) => Ok(session),
+            AsyncResult::Failed(f) => Err(io::Error::other(format!(
+                "Could not start session {}: {}",
+                args.build.session, f.message.message
+            ))),
+            AsyncResult::Error(e) => Err(io::Error::other(format!(
+                "Could not start session {}: {}",
+                args.build.session, e.message
+            ))),
+        }
+    }
+
+    /// Internal identification of the session object within the server process.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Id of the task that started the session.
+    pub fn task(&self) -> &str {
+        &self.task
+    }
+
+    /// Temporary directory that is specifically created for this session and deleted after it has been stopped.
+    /// It is the default `master_dir` for `use_theories` and `purge_theories`.
+    pub fn tmp_dir(&self) -> Option<&str> {
+        self.tmp_dir.as_deref()
+    }
+
+    /// Adds the current version of the given theory files to the session.
+    pub fn use_theories(&mut self, theories: &[&str]) -> AsyncTask<'static, UseTheoryResults, ()> {
+        self.use_theories_with(UseTheoriesArgs::for_session(&self.id, theories))
+    }
+
+    /// Runs `use_theories` with the given arguments within this session.
+    /// The `session_id` of `args` is replaced by the id of this session.
+    pub fn use_theories_with(
+        &mut self,
+        mut args: UseTheoriesArgs,
+    ) -> AsyncTask<'static, UseTheoryResults, ()> {
+        args.session_id = self.id.clone();
+        self.client.start_task(Command {
+            name: "use_theories".to_owned(),
+            args: Some(args),
+        })
+    }
+
+    /// Removes the given theories from the session.
+    pub async fn purge_theories(
+        &mut self,
+        theories: &[&str],
+    ) -> Result<SyncResult<PurgeTheoryResults, ()>, io::Error> {
+        self.purge_theories_with(PurgeTheoryArgs::for_session(&self.id, theories))
+            .await
+    }
+
+    /// Runs `purge_theories` with the given arguments within this session.
+    /// The `session_id` of `args` is replaced by the id of this session.
+    pub async fn purge_theories_with(
+        &mut self,
+        mut args: PurgeTheoryArgs,
+    ) -> Result<SyncResult<PurgeTheoryResults, ()>, io::Error> {
+        args.session_id = self.id.clone();
+        self.client.purge_theories(args).await
+    }
+
+    /// Stops the session on the server.
+    ///
+    /// If communication with the server fails or the future is dropped before the server answered,
+    /// the session is stopped on a best-effort basis as if it was dropped.
+    pub async fn stop(
+        mut self,
+    ) -> Result<AsyncResult<SessionStopResult, SessionStopResult>, io::Error> {
+        let args = SessionStopArgs {
+            session_id: self.id.clone(),
+        };
+        let res = self.client.session_stop(&args).await?;
+        self.stopped = true;
+        Ok(res)
+    }
+
+    /// Drops the session without stopping it, e.g., because its server is gone.
+    pub(crate) fn forget(mut self) {
+        self.stopped = true;
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("id", &self.id)
+            .field("task", &self.task)
+            .field("tmp_dir", &self.tmp_dir)
+            .finish()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.stopped {
+            return;
+        }
+        log::warn!(
+            "Session {} was dropped without being stopped, stopping it",
+            self.id
+        );
+        let client = self.client.clone();
+        let id = self.id.clone();
+        // Do not block the (possibly async) context the session is dropped in
+        std::thread::spawn(move || {
+            let cmd = Command {
+                name: "session_stop".to_owned(),
+                args: Some(SessionStopArgs {
+                    session_id: id.clone(),
+                }),
+            };
+            if let Err(e) = client.dispatch_blocking(&cmd, Duration::from_secs(5)) {
+                log::warn!("Could not stop session {}: {}", id, e);
+            }
+        });
+    }
+}
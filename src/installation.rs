@@ -0,0 +1,365 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// An Isabelle installation, identified by its `isabelle` executable.
+///
+/// The default installation runs the bare `isabelle` command, i.e., the first one found in `PATH` when a process is spawned.
+/// Use [Installation::discover] or one of the other constructors to select an installation explicitly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Installation {
+    executable: PathBuf,
+    /// Private user home, see [Installation::isolated]
+    home: Option<Arc<IsolatedHome>>,
+}
+
+impl Default for Installation {
+    fn default() -> Self {
+        Self {
+            executable: PathBuf::from("isabelle"),
+            home: None,
+        }
+    }
+}
+
+/// Temporary `ISABELLE_HOME_USER` directory of an isolated installation, deleted when dropped.
+#[derive(Debug, PartialEq, Eq)]
+struct IsolatedHome {
+    dir: PathBuf,
+}
+
+impl IsolatedHome {
+    /// Creates a new, empty directory in the system's temporary directory.
+    fn create() -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let dir = env::temp_dir().join(format!(
+                "isabelle-home-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok(Self { dir }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for IsolatedHome {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            log::warn!("Could not remove {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+impl Installation {
+    /// Uses the installation at `path`, which is either the Isabelle home directory or the `isabelle` executable itself.
+    ///
+    /// Returns an `io::Error` of kind `NotFound` if `path` contains no `isabelle` executable.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let executable = if path.is_dir() {
+            path.join("bin").join("isabelle")
+        } else {
+            path.to_path_buf()
+        };
+        if !executable.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No Isabelle executable at {}", executable.display()),
+            ));
+        }
+        Ok(Self {
+            executable,
+            home: None,
+        })
+    }
+
+    /// Uses the installation the `ISABELLE_HOME` environment variable points to.
+    pub fn from_env() -> io::Result<Self> {
+        match env::var_os("ISABELLE_HOME") {
+            Some(home) => Self::from_path(home),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "ISABELLE_HOME is not set",
+            )),
+        }
+    }
+
+    /// Uses the first `isabelle` executable found in `PATH`.
+    pub fn from_path_lookup() -> io::Result<Self> {
+        env::var_os("PATH")
+            .iter()
+            .flat_map(env::split_paths)
+            .map(|dir| dir.join("isabelle"))
+            .find(|exe| exe.is_file())
+            .map(|executable| Self {
+                executable,
+                home: None,
+            })
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "No isabelle executable in PATH")
+            })
+    }
+
+    /// Scans common install locations for Isabelle installations, such as `/opt/Isabelle2022` or `~/Isabelle2022`.
+    /// Returns the installations found, newest first.
+    pub fn scan() -> Vec<Self> {
+        let mut bases = vec![
+            PathBuf::from("/opt"),
+            PathBuf::from("/usr/local"),
+            PathBuf::from("/Applications"),
+        ];
+        if let Some(home) = env::var_os("HOME") {
+            bases.push(PathBuf::from(home));
+        }
+
+        let mut found = bases
+            .iter()
+            .filter_map(|base| fs::read_dir(base).ok())
+            .flatten()
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if !name.starts_with("Isabelle") {
+                    return None;
+                }
+                // On macOS, the installation resides within the application bundle
+                let home = match name.strip_suffix(".app") {
+                    Some(stem) => entry.path().join("Contents").join("Resources").join(stem),
+                    None => entry.path(),
+                };
+                Self::from_path(home).ok().map(|inst| (name, inst))
+            })
+            .collect::<Vec<_>>();
+        found.sort_by(|(a, _), (b, _)| b.cmp(a));
+        found.into_iter().map(|(_, inst)| inst).collect()
+    }
+
+    /// Finds an installation using, in that order, `ISABELLE_HOME`, the `PATH`, and the common install locations.
+    pub fn discover() -> io::Result<Self> {
+        Self::from_env()
+            .or_else(|_| Self::from_path_lookup())
+            .or_else(|_| {
+                Self::scan().into_iter().next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "No Isabelle installation found")
+                })
+            })
+    }
+
+    /// Returns a copy of this installation that runs against a private, temporary `ISABELLE_HOME_USER`,
+    /// such that its processes neither see nor modify the user's servers registry, heaps, and preferences.
+    /// Other state in the user's `HOME` is not affected.
+    ///
+    /// Session heaps in `shared_heaps`, e.g., `~/.isabelle/Isabelle2022/heaps`, are linked into the private `ISABELLE_HEAPS`,
+    /// in addition to the heaps of the installation (`ISABELLE_HEAPS_SYSTEM`).
+    /// Heaps built by the isolated installation replace the links, leaving the shared heaps untouched.
+    /// Linking the heaps is only supported on Unix.
+    ///
+    /// The private home is deleted when the last clone of the returned installation is dropped.
+    /// Returns an `io::Error` if the settings of the installation do not respect `ISABELLE_HOME_USER` from the environment.
+    pub fn isolated(&self, shared_heaps: Option<&Path>) -> io::Result<Self> {
+        let isolated = Self {
+            executable: self.executable.clone(),
+            home: Some(Arc::new(IsolatedHome::create()?)),
+        };
+        let home = isolated.isolated_home().unwrap();
+        let home_user = PathBuf::from(isolated.getenv("ISABELLE_HOME_USER")?);
+        if home_user != home {
+            return Err(io::Error::other(format!(
+                "Installation uses ISABELLE_HOME_USER {} instead of {}",
+                home_user.display(),
+                home.display()
+            )));
+        }
+
+        if let Some(heaps) = shared_heaps {
+            link_tree(heaps, &PathBuf::from(isolated.getenv("ISABELLE_HEAPS")?))?;
+        }
+        Ok(isolated)
+    }
+
+    /// Returns the private `ISABELLE_HOME_USER` if the installation is isolated, see [Installation::isolated].
+    pub fn isolated_home(&self) -> Option<&Path> {
+        self.home.as_ref().map(|h| h.dir.as_path())
+    }
+
+    /// Returns the path of the `isabelle` executable.
+    pub fn executable(&self) -> &Path {
+        &self.executable
+    }
+
+    /// Returns the version of the installation, e.g., `Isabelle2022` (`isabelle version`).
+    pub fn version(&self) -> io::Result<String> {
+        self.tool_output(&["version"])
+    }
+
+    /// Returns the identifier of the installation, e.g., `Isabelle2022` (`ISABELLE_IDENTIFIER`).
+    /// It is empty for development versions.
+    pub fn identifier(&self) -> io::Result<String> {
+        self.getenv("ISABELLE_IDENTIFIER")
+    }
+
+    /// Returns the value of the Isabelle setting `name` (`isabelle getenv -b`).
+    pub fn getenv(&self, name: &str) -> io::Result<String> {
+        self.tool_output(&["getenv", "-b", name])
+    }
+
+    /// Runs an Isabelle tool with `args` and returns its trimmed stdout.
+    fn tool_output(&self, args: &[&str]) -> io::Result<String> {
+        tool_output(self.command(), args)
+    }
+
+    /// Creates a command running the `isabelle` executable.
+    pub(crate) fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.executable);
+        if let Some(home) = &self.home {
+            cmd.env("ISABELLE_HOME_USER", &home.dir);
+        }
+        cmd
+    }
+
+    /// Creates an asynchronous command running the `isabelle` executable.
+    pub(crate) fn tokio_command(&self) -> tokio::process::Command {
+        let mut cmd = tokio::process::Command::new(&self.executable);
+        if let Some(home) = &self.home {
+            cmd.env("ISABELLE_HOME_USER", &home.dir);
+        }
+        cmd
+    }
+}
+
+/// Runs the `isabelle` command `cmd` with `args` and returns its trimmed stdout.
+fn tool_output(mut cmd: Command, args: &[&str]) -> io::Result<String> {
+    let output = cmd.args(args).stderr(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "isabelle {} failed with {}",
+            args.join(" "),
+            output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Recreates the directory tree `source` at `target`, with symbolic links to the files of `source`.
+fn link_tree(source: &Path, target: &Path) -> io::Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let target = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_tree(&entry.path(), &target)?;
+        } else {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(entry.path(), &target)?;
+            #[cfg(not(unix))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Shared heaps are only supported on Unix",
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::test_util;
+
+    /// Creates a fake Isabelle installation that answers `version` and `getenv`, returns its home directory.
+    fn fake_installation(name: &str) -> PathBuf {
+        let installation = test_util::fake_installation(
+            name,
+            concat!(
+                "case \"$1 $3\" in\n",
+                "  version*) echo Isabelle2099 ;;\n",
+                "  \"getenv ISABELLE_HOME_USER\") echo \"${ISABELLE_HOME_USER:-$HOME/.isabelle/Isabelle2099-id}\" ;;\n",
+                "  \"getenv ISABELLE_HEAPS\") echo \"${ISABELLE_HOME_USER:-$HOME/.isabelle/Isabelle2099-id}/heaps\" ;;\n",
+                "  \"getenv HOME\") echo \"$HOME\" ;;\n",
+                "  getenv*) echo Isabelle2099-id ;;\n",
+                "esac",
+            ),
+        );
+        test_util::home(&installation).to_path_buf()
+    }
+
+    #[test]
+    fn test_from_path() {
+        let home = fake_installation("isabelle-client-from-path");
+        let inst = Installation::from_path(&home).unwrap();
+        assert_eq!(inst.executable(), home.join("bin").join("isabelle"));
+        assert_eq!(
+            Installation::from_path(home.join("bin").join("isabelle")).unwrap(),
+            inst
+        );
+        assert_eq!(inst.version().unwrap(), "Isabelle2099");
+        assert_eq!(inst.identifier().unwrap(), "Isabelle2099-id");
+    }
+
+    #[test]
+    fn test_isolated() {
+        let home = fake_installation("isabelle-client-isolated");
+        let shared = home.join("shared-heaps");
+        fs::create_dir_all(shared.join("polyml").join("log")).unwrap();
+        fs::write(shared.join("polyml").join("HOL"), "heap").unwrap();
+        fs::write(shared.join("polyml").join("log").join("HOL.db"), "db").unwrap();
+
+        let inst = Installation::from_path(&home).unwrap();
+        let isolated = inst.isolated(Some(&shared)).unwrap();
+        assert_eq!(inst.isolated_home(), None);
+
+        let home_user = isolated.isolated_home().unwrap().to_path_buf();
+        assert!(home_user.starts_with(env::temp_dir()));
+        assert_eq!(
+            isolated.getenv("ISABELLE_HOME_USER").unwrap(),
+            home_user.display().to_string()
+        );
+        // Only the Isabelle home is private
+        assert_eq!(
+            isolated.getenv("HOME").unwrap(),
+            env::var("HOME").unwrap_or_default()
+        );
+
+        let heap = home_user.join("heaps").join("polyml").join("HOL");
+        assert_eq!(
+            fs::read_link(&heap).unwrap(),
+            shared.join("polyml").join("HOL")
+        );
+        assert_eq!(
+            fs::read_to_string(
+                home_user
+                    .join("heaps")
+                    .join("polyml")
+                    .join("log")
+                    .join("HOL.db")
+            )
+            .unwrap(),
+            "db"
+        );
+
+        // The private home is deleted with the last clone
+        let clone = isolated.clone();
+        drop(isolated);
+        assert!(home_user.exists());
+        drop(clone);
+        assert!(!home_user.exists());
+        assert!(shared.join("polyml").join("HOL").exists());
+    }
+
+    #[test]
+    fn test_from_path_missing() {
+        let err = Installation::from_path("/nonexistent/Isabelle").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
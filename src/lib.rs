@@ -1,7 +1,13 @@
+pub mod build_log;
 pub mod client;
+pub mod installation;
+pub mod limits;
 pub mod process;
 pub mod server;
 
+#[cfg(all(test, unix))]
+pub(crate) mod test_util;
+
 /// Runs rust code in readme as doc-tests
 #[cfg(doctest)]
 mod test_readme {
@@ -0,0 +1,249 @@
+use std::{io, process::Child, time::Duration};
+
+/// Resource limits for Isabelle processes.
+///
+/// The limits are set on the `isabelle` process using `setrlimit` and are inherited by all of its children,
+/// such as the Poly/ML processes, where they apply to each process individually.
+/// They are only supported on Unix and ignored on other platforms.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Maximum size of the virtual memory of a process in bytes (`RLIMIT_AS`).
+    ///
+    /// The JVM of the Isabelle server reserves far more virtual memory than it uses and fails to start under a tight limit,
+    /// so this is mostly useful for batch processes, see [crate::process::ProcessArgs].
+    pub memory: Option<u64>,
+    /// Maximum CPU time of a process (`RLIMIT_CPU`), rounded up to whole seconds
+    pub cpu_time: Option<Duration>,
+}
+
+impl ResourceLimits {
+    /// Limits the virtual memory of each process to `bytes`, see [ResourceLimits::memory](#structfield.memory).
+    pub fn memory(mut self, bytes: u64) -> Self {
+        self.memory = Some(bytes);
+        self
+    }
+
+    /// Limits the CPU time of each process to `time`.
+    pub fn cpu_time(mut self, time: Duration) -> Self {
+        self.cpu_time = Some(time);
+        self
+    }
+
+    /// Applies the limits to the current process, i.e., the child between `fork` and `exec`.
+    #[cfg(unix)]
+    fn apply(&self) -> io::Result<()> {
+        let set = |resource, value: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: value as libc::rlim_t,
+                rlim_max: value as libc::rlim_t,
+            };
+            // SAFETY: `setrlimit` is async-signal-safe and `limit` outlives the call
+            if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        };
+        if let Some(memory) = self.memory {
+            set(libc::RLIMIT_AS, memory)?;
+        }
+        if let Some(time) = self.cpu_time {
+            let secs = time.as_secs() + u64::from(time.subsec_nanos() > 0);
+            set(libc::RLIMIT_CPU, secs)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the process of `cmd` in a new process group with the given limits,
+/// such that the whole process tree can be killed with [kill_tree].
+pub(crate) fn isolate(cmd: &mut std::process::Command, limits: ResourceLimits) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+        if limits != ResourceLimits::default() {
+            // SAFETY: the closure only calls `setrlimit`
+            unsafe { cmd.pre_exec(move || limits.apply()) };
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (cmd, limits);
+}
+
+/// Asynchronous version of [isolate].
+pub(crate) fn isolate_async(cmd: &mut tokio::process::Command, limits: ResourceLimits) {
+    #[cfg(unix)]
+    {
+        cmd.process_group(0);
+        if limits != ResourceLimits::default() {
+            // SAFETY: the closure only calls `setrlimit`
+            unsafe { cmd.pre_exec(move || limits.apply()) };
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (cmd, limits);
+}
+
+/// Kills all processes in the process group led by the process `pid`.
+/// On Linux, also kills the descendants of `pid` that have left the group, e.g., by `setsid`,
+/// as long as they have not been reparented because their parent has terminated.
+pub(crate) fn kill_group(pid: u32) -> io::Result<()> {
+    // Collect the descendants before the kill reparents them
+    #[cfg(target_os = "linux")]
+    let descendants = descendants(pid);
+    #[cfg(unix)]
+    {
+        // SAFETY: `killpg` has no memory safety preconditions
+        if unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) } != 0 {
+            let e = io::Error::last_os_error();
+            // The group is empty
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                return Err(e);
+            }
+        }
+    }
+    #[cfg(target_os = "linux")]
+    for pid in descendants {
+        // SAFETY: `kill` has no memory safety preconditions
+        // Failures are ignored, the process may have been killed with the group or terminated in the meantime
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
+    Ok(())
+}
+
+/// Returns the pids of all descendants of the process `pid`, according to the parent pids in `/proc/*/stat`.
+#[cfg(target_os = "linux")]
+fn descendants(pid: u32) -> Vec<u32> {
+    let mut children = std::collections::HashMap::<u32, Vec<u32>>::new();
+    for entry in std::fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let Some(child) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        // The parent pid is the second field after the parenthesised command name, which may contain spaces
+        let parent = std::fs::read_to_string(entry.path().join("stat"))
+            .ok()
+            .and_then(|stat| {
+                let (_, rest) = stat.rsplit_once(')')?;
+                rest.split_whitespace().nth(1)?.parse::<u32>().ok()
+            });
+        if let Some(parent) = parent {
+            children.entry(parent).or_default().push(child);
+        }
+    }
+
+    let mut result = Vec::new();
+    let mut pending = vec![pid];
+    while let Some(pid) = pending.pop() {
+        if let Some(children) = children.remove(&pid) {
+            result.extend(&children);
+            pending.extend(children);
+        }
+    }
+    result
+}
+
+/// Kills the process `child` along with all processes in its process group, and waits for it to terminate.
+/// `child` must have been spawned from a command set up by [isolate].
+pub(crate) fn kill_tree(child: &mut Child) -> io::Result<()> {
+    kill_group(child.id())?;
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
+
+/// Kills a process group when dropped, unless it was disarmed.
+pub(crate) struct GroupGuard(Option<u32>);
+
+impl GroupGuard {
+    pub(crate) fn new(pid: Option<u32>) -> Self {
+        Self(pid)
+    }
+
+    pub(crate) fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for GroupGuard {
+    fn drop(&mut self) {
+        if let Some(pid) = self.0 {
+            if let Err(e) = kill_group(pid) {
+                log::warn!("Could not kill process group {}: {}", pid, e);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Command, Stdio},
+    };
+
+    fn spawn(script: &str, limits: ResourceLimits) -> (Child, Vec<String>) {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script).stdout(Stdio::piped());
+        isolate(&mut cmd, limits);
+        let mut child = cmd.spawn().unwrap();
+        let lines = BufReader::new(child.stdout.take().unwrap())
+            .lines()
+            .take(2)
+            .map(Result::unwrap)
+            .collect();
+        (child, lines)
+    }
+
+    #[test]
+    fn test_limits_are_applied() {
+        let limits = ResourceLimits::default()
+            .memory(1 << 30)
+            .cpu_time(Duration::from_millis(1500));
+        let (mut child, lines) = spawn("ulimit -v; ulimit -t", limits);
+        assert_eq!(lines, vec!["1048576", "2"]);
+        child.wait().unwrap();
+    }
+
+    /// Waits up to a second for the process `pid` to be gone or a zombie waiting to be reaped.
+    fn dead(pid: &str) -> bool {
+        let dead = || {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid));
+            stat.map_or(true, |s| s.contains(") Z "))
+        };
+        for _ in 0..50 {
+            if dead() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        dead()
+    }
+
+    #[test]
+    fn test_kill_tree_with_new_session() {
+        let (mut child, lines) = spawn(
+            "setsid sleep 30 & echo $!; echo started; wait",
+            ResourceLimits::default(),
+        );
+        assert_eq!(lines[1], "started");
+
+        kill_tree(&mut child).unwrap();
+        assert!(dead(&lines[0]));
+    }
+
+    #[test]
+    fn test_kill_tree() {
+        let (mut child, lines) = spawn(
+            "sleep 30 & echo $!; cut -d' ' -f5 /proc/$$/stat; wait",
+            ResourceLimits::default(),
+        );
+        // The process leads its own group
+        assert_eq!(lines[1], child.id().to_string());
+
+        kill_tree(&mut child).unwrap();
+        assert!(dead(&lines[0]));
+    }
+}
@@ -2,12 +2,40 @@ use std::{
     collections::HashMap,
     io,
     path::PathBuf,
-    process::{Output, Stdio},
+    process::{ExitStatus, Output, Stdio},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime},
 };
-use tokio::process::Command;
+
+use tokio::{
+    io::{AsyncRead, BufReader},
+    process::Child,
+    sync::{mpsc, Notify},
+    time::Instant,
+};
+
+use crate::client::results::{Message, Position, SessionBuildResult, SessionBuildResults, Timing};
+use crate::installation::Installation;
+use crate::limits::{self, GroupGuard, ResourceLimits};
+use crate::server::{
+    output::{read_line_async, OutputLine, OutputSource},
+    unique_name,
+};
+
+/// Time to wait for the output streams of a terminated process to be closed.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// ML source evaluated by the raw ML process on startup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MlSource {
+    /// An ML expression (-e)
+    Expr(String),
+    /// An ML file (-f)
+    File(String),
+}
 
 /// Arguments for running the raw ML process in batch mode.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct ProcessArgs {
     /// The theories to load (-T). Multiple theories are loaded in the given order.
     pub theories: Vec<String>,
@@ -15,9 +43,19 @@ pub struct ProcessArgs {
     pub session_dirs: Vec<String>,
     /// The The logic session name (default is ISABELLE_LOGIC="HOL")
     pub logic: Option<String>,
+    /// ML expressions (-e) and files (-f) to evaluate after loading the theories, in the given order.
+    pub ml: Vec<MlSource>,
+    /// Print modes for the output (-m)
+    pub modes: Vec<String>,
     /// Override Isabelle system options for this process (-d).
     /// Use [OptionsBuilder] to construct options.
     pub options: HashMap<String, String>,
+    /// The Isabelle installation to run (default is `isabelle` from `PATH`)
+    pub installation: Installation,
+    /// Resource limits for the process and its children
+    pub limits: ResourceLimits,
+    /// Wall-clock time after which the process and its children are killed
+    pub timeout: Option<Duration>,
 }
 
 impl ProcessArgs {
@@ -27,12 +65,81 @@ impl ProcessArgs {
             ..Default::default()
         }
     }
+
+    /// Arguments for evaluating the given ML expression.
+    pub fn eval(expr: &str) -> Self {
+        Self {
+            ml: vec![MlSource::Expr(expr.to_owned())],
+            ..Default::default()
+        }
+    }
+}
+
+/// Evaluates the ML expression `expr` in the logic image of `args`, after its theories and ML sources,
+/// and returns the value printed by the ML toplevel, e.g., `val it = 2: int` for `1 + 1`.
+/// Output of the theories and the other ML sources is not included.
+///
+/// Returns an error of kind [io::ErrorKind::Other] containing the output of the process if the evaluation failed.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use isabelle_client::process::{eval_ml, ProcessArgs};
+/// # tokio_test::block_on(async {
+///
+/// let args = ProcessArgs {
+///     logic: Some("HOL".to_owned()),
+///     ..Default::default()
+/// };
+/// let value = eval_ml(&args, "Context.this_theory (Context.the_global_context ())").await.unwrap();
+/// # })
+/// ```
+pub async fn eval_ml(args: &ProcessArgs, expr: &str) -> io::Result<String> {
+    // The value is printed between two unique marker lines
+    let marker = unique_name("isabelle-client-eval");
+    let (begin, end) = (format!("{}-begin", marker), format!("{}-end", marker));
+    let mut args = args.clone();
+    args.ml.extend([
+        MlSource::Expr(format!("val _ = writeln \"{}\"", begin)),
+        MlSource::Expr(expr.to_owned()),
+        MlSource::Expr(format!("val _ = writeln \"{}\"", end)),
+    ]);
+    let output = spawn_process(&args, None)?.output().await?;
+    let stdout = output.text(OutputSource::Stdout);
+    match between(&stdout, &begin, &end) {
+        Some(value) if output.success() => Ok(value),
+        _ => Err(io::Error::other(format!(
+            "Evaluation of ML expression failed ({:?}): {}{}",
+            output.status,
+            stdout,
+            output.text(OutputSource::Stderr)
+        ))),
+    }
+}
+
+/// Returns the trimmed lines of `text` strictly between the lines `begin` and `end`.
+fn between(text: &str, begin: &str, end: &str) -> Option<String> {
+    let mut lines = text.lines();
+    lines.by_ref().find(|l| l.trim() == begin)?;
+    let mut value = Vec::new();
+    for line in lines {
+        if line.trim() == end {
+            return Some(value.join("\n").trim().to_owned());
+        }
+        value.push(line);
+    }
+    None
 }
 
 /// Runs the raw ML process in batch mode.
 /// Arguments for the command are specified in [ProcessArgs].
 /// Returns the process' output.
 ///
+/// The process runs in its own process group.
+/// If the returned future is dropped before the process has terminated, the whole process tree is killed.
+/// If the timeout of `args` elapses, the process tree is killed as well and an error of kind [io::ErrorKind::TimedOut] is returned.
+/// To obtain the output collected until then, use [spawn_process] and [BatchProcess::output] instead.
+///
 /// # Example
 ///
 /// ```rust
@@ -50,7 +157,81 @@ pub async fn batch_process(
     args: &ProcessArgs,
     current_dir: Option<&PathBuf>,
 ) -> io::Result<Output> {
-    let mut isabelle_cmd = Command::new("isabelle");
+    let child = command(args, current_dir).spawn()?;
+    // Kills the process tree if the future is dropped or times out
+    let mut group = GroupGuard::new(child.id());
+    let output = match args.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Process did not terminate within {:?}", timeout),
+                )
+            })??,
+        None => child.wait_with_output().await?,
+    };
+    group.disarm();
+    Ok(output)
+}
+
+/// Runs the raw ML process in batch mode like [batch_process], but streams its output.
+/// Returns a [BatchProcess] that yields the lines of stdout and stderr as they arrive.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use isabelle_client::process::{spawn_process, ProcessArgs};
+/// # tokio_test::block_on(async {
+///
+/// let args = ProcessArgs::load_theories(&[String::from("~~/src/HOL/Examples/Drinker")]);
+/// let mut process = spawn_process(&args, None).unwrap();
+/// while let Some(line) = process.next_line().await {
+///     println!("{:?}: {}", line.source, line.line);
+/// }
+/// assert!(process.wait().await.unwrap().success());
+/// # })
+/// ```
+pub fn spawn_process(
+    args: &ProcessArgs,
+    current_dir: Option<&PathBuf>,
+) -> io::Result<BatchProcess> {
+    spawn(command(args, current_dir), args.timeout, false)
+}
+
+/// Spawns `cmd` as a [BatchProcess] that is killed once `timeout` has elapsed.
+/// If `retain` is set, the lines returned by [BatchProcess::next_line] are kept for [BatchProcess::output].
+fn spawn(
+    mut cmd: tokio::process::Command,
+    timeout: Option<Duration>,
+    retain: bool,
+) -> io::Result<BatchProcess> {
+    let mut child = cmd.spawn()?;
+    let group = GroupGuard::new(child.id());
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let (tx, lines) = mpsc::unbounded_channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward(OutputSource::Stdout, stdout, tx.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward(OutputSource::Stderr, stderr, tx);
+    }
+    Ok(BatchProcess {
+        kill: KillHandle {
+            pid: Arc::new(Mutex::new(child.id())),
+            killed: Arc::new(Notify::new()),
+        },
+        child,
+        lines,
+        received: retain.then(Vec::new),
+        group,
+        deadline,
+    })
+}
+
+/// Returns the `isabelle process` command for `args`.
+fn command(args: &ProcessArgs, current_dir: Option<&PathBuf>) -> tokio::process::Command {
+    let mut isabelle_cmd = args.installation.tokio_command();
 
     isabelle_cmd
         .arg("process")
@@ -72,11 +253,556 @@ pub async fn batch_process(
         isabelle_cmd.arg("-l").arg(l);
     }
 
+    for ml in &args.ml {
+        match ml {
+            MlSource::Expr(e) => isabelle_cmd.arg("-e").arg(e),
+            MlSource::File(f) => isabelle_cmd.arg("-f").arg(f),
+        };
+    }
+    for m in &args.modes {
+        isabelle_cmd.arg("-m").arg(m);
+    }
+
     for (k, v) in args.options.iter() {
         isabelle_cmd.arg("-o").arg(format!("{}={}", k, v));
     }
 
-    isabelle_cmd.spawn()?.wait_with_output().await
+    limits::isolate_async(&mut isabelle_cmd, args.limits);
+    isabelle_cmd
+}
+
+/// Sends the lines read from `reader` to `tx` until the stream ends.
+fn forward<R: AsyncRead + Unpin + Send + 'static>(
+    source: OutputSource,
+    reader: R,
+    tx: mpsc::UnboundedSender<OutputLine>,
+) {
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        loop {
+            // Invalid UTF-8 is replaced, such that the output is read until the process closes it
+            let line = match read_line_async(&mut reader, &mut buf).await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    log::debug!("Could not read {:?} of process: {}", source, e);
+                    break;
+                }
+            };
+            let line = OutputLine {
+                source,
+                line,
+                time: SystemTime::now(),
+            };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// A running `isabelle process` or `isabelle build`, started by [spawn_process] or [spawn_build].
+///
+/// If it is dropped before the process has terminated, the whole process tree is killed.
+pub struct BatchProcess {
+    child: Child,
+    lines: mpsc::UnboundedReceiver<OutputLine>,
+    /// Lines returned by [BatchProcess::next_line], kept for [BatchProcess::output] if retained
+    received: Option<Vec<OutputLine>>,
+    group: GroupGuard,
+    kill: KillHandle,
+    /// Time at which the process is killed by [BatchProcess::output]
+    deadline: Option<Instant>,
+}
+
+impl BatchProcess {
+    /// Returns the OS identifier of the process, or `None` if it has already been waited for.
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    /// Returns the next line the process wrote to stdout or stderr,
+    /// or `None` once both streams are closed.
+    /// Lines that were not received yet are buffered.
+    pub async fn next_line(&mut self) -> Option<OutputLine> {
+        let line = self.lines.recv().await?;
+        if let Some(received) = self.received.as_mut() {
+            received.push(line.clone());
+        }
+        Some(line)
+    }
+
+    /// Waits for the process to terminate and returns its exit status.
+    /// Lines not received yet remain available from [BatchProcess::next_line].
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child.wait().await?;
+        self.group.disarm();
+        self.kill.pid.lock().unwrap().take();
+        Ok(status)
+    }
+
+    /// Kills the process along with all processes it spawned, and waits for it to terminate.
+    pub async fn kill(&mut self) -> io::Result<()> {
+        self.kill.kill()?;
+        self.child.kill().await?;
+        self.group.disarm();
+        Ok(())
+    }
+
+    /// Returns a handle for killing the process from another task.
+    pub fn kill_handle(&self) -> KillHandle {
+        self.kill.clone()
+    }
+
+    /// Collects the output of the process until it terminates, is killed through a [KillHandle],
+    /// or its timeout elapses.
+    /// In the latter cases, the whole process tree is killed and the output collected so far is returned.
+    ///
+    /// Lines already received by [BatchProcess::next_line] are only included for builds started by [spawn_build],
+    /// such that [BatchOutput::build_results] sees all of them.
+    pub async fn output(mut self) -> io::Result<BatchOutput> {
+        let mut lines = self.received.take().unwrap_or_default();
+        let killed = self.kill.killed.clone();
+        let deadline = self.deadline;
+        let deadline = async move {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(deadline);
+        let status = loop {
+            tokio::select! {
+                line = self.lines.recv() => match line {
+                    Some(line) => lines.push(line),
+                    None => break None,
+                },
+                _ = &mut deadline => break Some(BatchStatus::TimedOut),
+                _ = killed.notified() => break Some(BatchStatus::Killed),
+            }
+        };
+        let status = match status {
+            Some(status) => {
+                self.kill().await?;
+                status
+            }
+            None => match self.wait().await? {
+                // The handle killed the process just before its streams were closed
+                _ if self.kill.was_killed() => BatchStatus::Killed,
+                status => BatchStatus::Exited(status),
+            },
+        };
+        // Collect the lines still in flight until the streams are closed,
+        // unless processes that escaped the kill keep them open
+        let rest = async {
+            while let Some(line) = self.lines.recv().await {
+                lines.push(line);
+            }
+        };
+        let _ = tokio::time::timeout(DRAIN_TIMEOUT, rest).await;
+        Ok(BatchOutput { status, lines })
+    }
+}
+
+/// A handle for killing a [BatchProcess] along with all processes it spawned.
+/// It can be cloned and sent to other tasks.
+#[derive(Clone)]
+pub struct KillHandle {
+    /// The process group, cleared once the process has been waited for
+    pid: Arc<Mutex<Option<u32>>>,
+    killed: Arc<Notify>,
+}
+
+impl KillHandle {
+    /// Kills the process tree, if the process has not terminated yet.
+    pub fn kill(&self) -> io::Result<()> {
+        let pid = self.pid.lock().unwrap();
+        if let Some(pid) = *pid {
+            limits::kill_group(pid)?;
+            self.killed.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Returns whether the process was killed by a handle.
+    fn was_killed(&self) -> bool {
+        // Consumes a pending notification
+        let notified = self.killed.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable()
+    }
+}
+
+/// How a [BatchProcess] terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchStatus {
+    /// The process terminated by itself or was killed by a signal from outside
+    Exited(ExitStatus),
+    /// The timeout elapsed and the process was killed
+    TimedOut,
+    /// The process was killed through a [KillHandle] or [BatchProcess::kill]
+    Killed,
+}
+
+/// Output of a [BatchProcess], see [BatchProcess::output].
+#[derive(Debug, Clone)]
+pub struct BatchOutput {
+    pub status: BatchStatus,
+    /// The lines of stdout and stderr in the order they were received
+    pub lines: Vec<OutputLine>,
+}
+
+impl BatchOutput {
+    /// Returns whether the process terminated by itself with exit code 0.
+    pub fn success(&self) -> bool {
+        matches!(self.status, BatchStatus::Exited(status) if status.success())
+    }
+
+    /// Returns the results of the sessions built by an `isabelle build` run with `args`, see [batch_build].
+    pub fn build_results(&self, args: &BuildArgs) -> SessionBuildResults {
+        let lines = self.lines.iter().map(|l| l.line.as_str());
+        build_results(lines, self.status, &args.sessions)
+    }
+
+    /// Returns the errors and warnings reported in the output, see [BatchMessages::from_output].
+    pub fn messages(&self) -> BatchMessages {
+        BatchMessages::from_output(self)
+    }
+
+    /// Returns the lines of the given stream, joined by newlines.
+    pub fn text(&self, source: OutputSource) -> String {
+        self.lines
+            .iter()
+            .filter(|l| l.source == source)
+            .map(|l| l.line.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Errors and warnings of an `isabelle process` run, parsed from its output.
+///
+/// Isabelle prefixes each line of an error with `***` and each line of a warning with `###`.
+/// Lines starting with `Bad theory import` are reported as errors as well.
+/// The position of a message is taken from its last `(line N of "file")` reference, e.g., in `At command "by" (line 5 of "~/Foo.thy")`.
+#[derive(Debug, Clone)]
+pub struct BatchMessages {
+    /// The process terminated successfully and reported no errors
+    pub ok: bool,
+    /// Errors and warnings in the order they were printed, using the message kinds of the server
+    pub messages: Vec<Message>,
+}
+
+impl BatchMessages {
+    /// Parses the given output lines of a process that terminated successfully if `success` is true.
+    pub fn parse<'a>(lines: impl IntoIterator<Item = &'a str>, success: bool) -> Self {
+        let mut messages = Vec::new();
+        let mut current: Option<(&str, Vec<&str>)> = None;
+        for line in lines {
+            let (kind, text) = if let Some(text) = line.strip_prefix("***") {
+                ("error", text.strip_prefix(' ').unwrap_or(text))
+            } else if let Some(text) = line.strip_prefix("###") {
+                ("warning", text.strip_prefix(' ').unwrap_or(text))
+            } else if line.starts_with("Bad theory import") {
+                messages.extend(current.take().and_then(message));
+                messages.extend(message(("error", vec![line])));
+                continue;
+            } else {
+                messages.extend(current.take().and_then(message));
+                continue;
+            };
+            match current.as_mut() {
+                Some((k, lines)) if *k == kind => lines.push(text),
+                _ => {
+                    messages.extend(current.take().and_then(message));
+                    current = Some((kind, vec![text]));
+                }
+            }
+            // The command reference ends a message
+            if text.starts_with("At command") {
+                messages.extend(current.take().and_then(message));
+            }
+        }
+        messages.extend(current.and_then(message));
+        let ok = success && !messages.iter().any(Message::is_error);
+        Self { ok, messages }
+    }
+
+    /// Parses the output collected by [BatchProcess::output], with the lines of stdout and stderr in the order they arrived.
+    pub fn from_output(output: &BatchOutput) -> Self {
+        Self::parse(
+            output.lines.iter().map(|l| l.line.as_str()),
+            output.success(),
+        )
+    }
+
+    /// Returns the error messages.
+    pub fn errors(&self) -> impl Iterator<Item = &Message> {
+        self.messages.iter().filter(|m| m.is_error())
+    }
+
+    /// Returns the warnings.
+    pub fn warnings(&self) -> impl Iterator<Item = &Message> {
+        self.messages.iter().filter(|m| m.is_warning())
+    }
+}
+
+/// Creates a message of the given kind from its lines, or `None` if they are blank.
+fn message((kind, lines): (&str, Vec<&str>)) -> Option<Message> {
+    let text = lines.join("\n").trim().to_owned();
+    if text.is_empty() {
+        return None;
+    }
+    static POS_RE: OnceLock<regex::Regex> = OnceLock::new();
+    let pos_re = POS_RE.get_or_init(|| {
+        regex::Regex::new(r#"\(line (\d+)(?: of "([^"]*)")?\)"#).expect("Invalid regex")
+    });
+    let pos = pos_re.captures_iter(&text).last().map(|c| Position {
+        line: c[1].parse().ok(),
+        file: c.get(2).map(|f| f.as_str().to_owned()),
+        ..Default::default()
+    });
+    Some(Message {
+        kind: kind.to_owned(),
+        message: text,
+        pos,
+    })
+}
+
+/// Arguments for building sessions with `isabelle build`.
+#[derive(Clone, Default)]
+pub struct BuildArgs {
+    /// The sessions to build
+    pub sessions: Vec<String>,
+    /// Include session directories (-d)
+    pub session_dirs: Vec<String>,
+    /// Override Isabelle system options (-o).
+    /// Use [OptionsBuilder] to construct options.
+    pub options: HashMap<String, String>,
+    /// Select all sessions (-a)
+    pub all: bool,
+    /// Build heap images (-b)
+    pub build: bool,
+    /// Clean build, i.e., remove the existing heaps and logs of the selected sessions first (-c)
+    pub clean: bool,
+    /// Soft build: only observe changes of sources, not heap images (-S)
+    pub soft: bool,
+    /// Exclude the given sessions and their descendants (-x)
+    pub exclude: Vec<String>,
+    /// Refer to the requirements of the selected sessions instead (-R)
+    pub requirements: bool,
+    /// Verbose mode, including the timing of each session (-v)
+    pub verbose: bool,
+    /// The Isabelle installation to run (default is `isabelle` from `PATH`)
+    pub installation: Installation,
+    /// Resource limits for the process and its children
+    pub limits: ResourceLimits,
+    /// Wall-clock time after which the build and its children are killed
+    pub timeout: Option<Duration>,
+}
+
+impl BuildArgs {
+    pub fn sessions(sessions: &[&str]) -> Self {
+        Self {
+            sessions: sessions.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Builds sessions with `isabelle build`.
+/// Arguments for the command are specified in [BuildArgs].
+/// Returns the results of the built sessions in the same form as [crate::client::IsabelleClient::session_build].
+///
+/// Use [spawn_build] to follow the progress of the build.
+/// If the timeout of `args` elapses, the process tree is killed and the sessions still running are reported as timed out.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use isabelle_client::process::{batch_build, BuildArgs};
+/// # tokio_test::block_on(async {
+///
+/// let mut args = BuildArgs::sessions(&["HOL-Library"]);
+/// args.build = true;
+/// let results = batch_build(&args, None).await.unwrap();
+/// assert!(results.ok);
+/// # })
+/// ```
+pub async fn batch_build(
+    args: &BuildArgs,
+    current_dir: Option<&PathBuf>,
+) -> io::Result<SessionBuildResults> {
+    let output = spawn_build(args, current_dir)?.output().await?;
+    Ok(output.build_results(args))
+}
+
+/// Runs `isabelle build` like [batch_build], but streams its output.
+/// The results are obtained from [BatchOutput::build_results] once the process terminated,
+/// which includes the lines already received by [BatchProcess::next_line].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use isabelle_client::process::{spawn_build, BuildArgs};
+/// # tokio_test::block_on(async {
+///
+/// let args = BuildArgs::sessions(&["HOL-Library"]);
+/// let mut build = spawn_build(&args, None).unwrap();
+/// while let Some(line) = build.next_line().await {
+///     println!("{}", line.line);
+/// }
+/// let results = build.output().await.unwrap().build_results(&args);
+/// # })
+/// ```
+pub fn spawn_build(args: &BuildArgs, current_dir: Option<&PathBuf>) -> io::Result<BatchProcess> {
+    let mut isabelle_cmd = args.installation.tokio_command();
+
+    isabelle_cmd
+        .arg("build")
+        .stderr(Stdio::piped())
+        .stdout(Stdio::piped());
+
+    if let Some(cd) = current_dir {
+        isabelle_cmd.current_dir(cd);
+    }
+
+    let flags = [
+        (args.all, "-a"),
+        (args.build, "-b"),
+        (args.clean, "-c"),
+        (args.requirements, "-R"),
+        (args.soft, "-S"),
+        (args.verbose, "-v"),
+    ];
+    for (_, flag) in flags.iter().filter(|(set, _)| *set) {
+        isabelle_cmd.arg(flag);
+    }
+    for d in &args.session_dirs {
+        isabelle_cmd.arg("-d").arg(d);
+    }
+    for (k, v) in args.options.iter() {
+        isabelle_cmd.arg("-o").arg(format!("{}={}", k, v));
+    }
+    for x in &args.exclude {
+        isabelle_cmd.arg("-x").arg(x);
+    }
+    isabelle_cmd.args(&args.sessions);
+
+    limits::isolate_async(&mut isabelle_cmd, args.limits);
+    spawn(isabelle_cmd, args.timeout, true)
+}
+
+/// Collects the results of the sessions reported in the output of `isabelle build`.
+///
+/// Sessions are reported by the lines `Building S ...` (`Running S ...` in recent versions),
+/// `Finished S (… elapsed time, … cpu time, …)` (without the cpu time for short sessions), and `S FAILED`,
+/// and, in verbose mode, `Timing S (…s elapsed time, …s cpu time, …s GC time, …)`.
+/// Requested sessions that are not reported, e.g., because they are up to date, succeeded if the build did.
+fn build_results<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+    status: BatchStatus,
+    requested: &[String],
+) -> SessionBuildResults {
+    let building_re =
+        regex::Regex::new(r"^(?:Building|Running) (\S+) (?:.* )?\.\.\.$").expect("Invalid regex");
+    let finished_re = regex::Regex::new(
+        r"^Finished (\S+) \((\d+):(\d+):(\d+) elapsed time(?:, (\d+):(\d+):(\d+) cpu time)?",
+    )
+    .expect("Invalid regex");
+    let timing_re = regex::Regex::new(
+        r"^Timing (\S+) \(.*?([\d.]+)s elapsed time(?:, ([\d.]+)s cpu time)?(?:, ([\d.]+)s GC time)?",
+    )
+    .expect("Invalid regex");
+    let failed_re = regex::Regex::new(r"^(\S+) FAILED").expect("Invalid regex");
+    // Optional parts that are missing count as zero
+    let number = |c: &regex::Captures, i: usize| {
+        c.get(i)
+            .and_then(|m| m.as_str().parse::<f64>().ok())
+            .unwrap_or_default()
+    };
+    let hms = |c: &regex::Captures, i: usize| {
+        number(c, i) * 3600.0 + number(c, i + 1) * 60.0 + number(c, i + 2)
+    };
+
+    let mut sessions: Vec<SessionBuildResult> = Vec::new();
+    let mut failed: Option<usize> = None;
+    let mut done = Vec::new();
+    for line in lines {
+        let name = [&building_re, &finished_re, &timing_re, &failed_re]
+            .iter()
+            .find_map(|re| re.captures(line))
+            .map(|c| c[1].to_owned());
+        let index = match name {
+            Some(name) => match sessions.iter().position(|s| s.session == name) {
+                Some(i) => i,
+                None => {
+                    sessions.push(session_result(&name, false, 1));
+                    sessions.len() - 1
+                }
+            },
+            None => {
+                // The reason of a failure is printed after the session
+                if let Some(i) = failed {
+                    if line.starts_with("***") && line.contains("Timeout") {
+                        sessions[i].timeout = true;
+                    }
+                }
+                continue;
+            }
+        };
+        let session = &mut sessions[index];
+        if let Some(c) = finished_re.captures(line) {
+            session.ok = true;
+            session.return_code = 0;
+            session.timing.elapsed = hms(&c, 2);
+            session.timing.cpu = hms(&c, 5);
+            done.push(index);
+        } else if let Some(c) = timing_re.captures(line) {
+            session.timing = Timing {
+                elapsed: number(&c, 2),
+                cpu: number(&c, 3),
+                gc: number(&c, 4),
+            };
+        } else if failed_re.is_match(line) {
+            session.ok = false;
+            failed = Some(index);
+            done.push(index);
+        }
+    }
+
+    let success = matches!(status, BatchStatus::Exited(s) if s.success());
+    if status == BatchStatus::TimedOut {
+        // Sessions still running when the build was killed
+        for (i, session) in sessions.iter_mut().enumerate() {
+            session.timeout |= !done.contains(&i);
+        }
+    }
+    for name in requested {
+        if !sessions.iter().any(|s| &s.session == name) {
+            sessions.push(session_result(name, success, usize::from(!success)));
+        }
+    }
+    let return_code = match status {
+        BatchStatus::Exited(s) => s.code().map_or(1, |c| c as usize),
+        BatchStatus::TimedOut | BatchStatus::Killed => 1,
+    };
+    SessionBuildResults {
+        ok: success && sessions.iter().all(|s| s.ok),
+        return_code,
+        sessions,
+    }
+}
+
+fn session_result(session: &str, ok: bool, return_code: usize) -> SessionBuildResult {
+    SessionBuildResult {
+        session: session.to_owned(),
+        ok,
+        return_code,
+        timeout: false,
+        timing: Timing::default(),
+    }
 }
 
 /// Builder that conveniently allows to specify common Isabelle options.
@@ -191,3 +917,291 @@ impl OptionsBuilder {
         self.add_bool_option("build_pide_reports", flag)
     }
 }
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::test_util::fake_installation;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn test_parse_messages() {
+        let output = r#"Loading theory "Draft.Foo"
+### Ignoring duplicate rewrite rule:
+### x = x
+*** Failed to finish proof (line 5 of "/tmp/Foo.thy"):
+*** goal (1 subgoal):
+***  1. False
+*** At command "by" (line 6 of "/tmp/Foo.thy")
+*** Undefined fact: "bar"
+val it = (): unit
+Bad theory import "Baz" (line 2 of "/tmp/Qux.thy")"#;
+        let res = BatchMessages::parse(output.lines(), true);
+        assert!(!res.ok);
+        assert_eq!(res.warnings().count(), 1);
+        assert_eq!(
+            res.messages[0].message,
+            "Ignoring duplicate rewrite rule:\nx = x"
+        );
+        assert_eq!(res.messages[0].pos, None);
+
+        let errors = res.errors().collect::<Vec<_>>();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].message.starts_with("Failed to finish proof"));
+        let pos = errors[0].pos.as_ref().unwrap();
+        assert_eq!(pos.line, Some(6));
+        assert_eq!(pos.theory(), Some("Foo"));
+        assert_eq!(errors[1].message, r#"Undefined fact: "bar""#);
+        assert_eq!(errors[2].pos.as_ref().unwrap().theory(), Some("Qux"));
+
+        let res = BatchMessages::parse("val it = 2: int".lines(), true);
+        assert!(res.ok);
+        assert!(res.messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_messages_in_arrival_order() {
+        let args = ProcessArgs {
+            installation: fake_installation(
+                "isabelle-client-messages",
+                "echo '### first'; sleep 0.1; echo '*** second' >&2; sleep 0.1; echo '### third'",
+            ),
+            ..Default::default()
+        };
+        let output = spawn_process(&args, None).unwrap().output().await.unwrap();
+        let res = BatchMessages::from_output(&output);
+        let messages = res
+            .messages
+            .iter()
+            .map(|m| m.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["first", "second", "third"]);
+        assert!(!res.ok);
+    }
+
+    #[test]
+    fn test_build_results() {
+        let output = r#"Building HOL-Library ...
+Finished HOL-Library (0:01:23 elapsed time, 0:04:56 cpu time, factor 3.57)
+Timing HOL-Library (8 threads, 83.125s elapsed time, 296.500s cpu time, 12.250s GC time, factor 3.57)
+Building Foo ...
+Foo FAILED (see also "isabelle log -H Error Foo")
+*** Timeout
+Running Bar (on localhost) ...
+Bar FAILED
+*** Undefined fact: "baz""#;
+        let requested = vec!["Foo".to_owned(), "HOL".to_owned()];
+        let status = ExitStatus::from_raw(1 << 8);
+        let res = build_results(output.lines(), BatchStatus::Exited(status), &requested);
+        assert!(!res.ok);
+        assert_eq!(res.return_code, 1);
+        let names = res
+            .sessions
+            .iter()
+            .map(|s| s.session.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["HOL-Library", "Foo", "Bar", "HOL"]);
+
+        let library = &res.sessions[0];
+        assert!(library.ok && !library.timeout);
+        assert_eq!(library.return_code, 0);
+        assert_eq!(library.timing.elapsed, 83.125);
+        assert_eq!(library.timing.gc, 12.25);
+        assert!(!res.sessions[1].ok && res.sessions[1].timeout);
+        assert!(!res.sessions[2].ok && !res.sessions[2].timeout);
+        assert!(!res.sessions[3].ok);
+
+        let output = "Building HOL-Library ...\nFinished HOL-Library (0:01:23 elapsed time, 0:04:56 cpu time, factor 3.57)";
+        let res = build_results(
+            output.lines(),
+            BatchStatus::Exited(ExitStatus::from_raw(0)),
+            &[],
+        );
+        assert!(res.ok);
+        assert_eq!(res.sessions[0].timing.elapsed, 83.0);
+        assert_eq!(res.sessions[0].timing.cpu, 296.0);
+
+        // Short sessions are reported without cpu time
+        let output = "Running Foo ...\nFinished Foo (0:00:05 elapsed time)\nTiming Foo (1 threads, 4.500s elapsed time, 3.250s cpu time, 0.125s GC time, factor 0.72)";
+        let res = build_results(
+            output.lines(),
+            BatchStatus::Exited(ExitStatus::from_raw(0)),
+            &["Foo".to_owned()],
+        );
+        assert!(res.ok);
+        assert_eq!(res.sessions.len(), 1);
+        assert!(res.sessions[0].ok);
+        assert_eq!(res.sessions[0].return_code, 0);
+        assert_eq!(res.sessions[0].timing.elapsed, 4.5);
+        assert_eq!(res.sessions[0].timing.cpu, 3.25);
+        assert_eq!(res.sessions[0].timing.gc, 0.125);
+
+        let res = build_results(
+            "Finished Foo (0:00:05 elapsed time)".lines(),
+            BatchStatus::Exited(ExitStatus::from_raw(0)),
+            &[],
+        );
+        assert!(res.sessions[0].ok);
+        assert_eq!(res.sessions[0].timing.elapsed, 5.0);
+        assert_eq!(res.sessions[0].timing.cpu, 0.0);
+
+        let res = build_results("Running Foo ...".lines(), BatchStatus::TimedOut, &[]);
+        assert!(!res.ok);
+        assert!(res.sessions[0].timeout);
+    }
+
+    #[tokio::test]
+    async fn test_batch_build() {
+        let mut args = BuildArgs::sessions(&["Foo"]);
+        args.build = true;
+        args.verbose = true;
+        args.exclude.push("Bar".to_owned());
+        args.installation = fake_installation(
+            "isabelle-client-build",
+            "echo \"$@\" >&2; echo 'Building Foo ...'; echo 'Finished Foo (0:00:05 elapsed time, 0:00:10 cpu time, factor 2.00)'",
+        );
+        let mut build = spawn_build(&args, None).unwrap();
+        let first = build.next_line().await.unwrap();
+        let output = build.output().await.unwrap();
+        assert_eq!(output.lines[0].line, first.line);
+        assert_eq!(output.text(OutputSource::Stderr), "build -b -v -x Bar Foo");
+        let res = output.build_results(&args);
+        assert!(res.ok);
+        assert_eq!(res.sessions.len(), 1);
+        assert_eq!(res.sessions[0].timing.elapsed, 5.0);
+
+        assert!(batch_build(&args, None).await.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_process() {
+        let args = ProcessArgs {
+            installation: fake_installation(
+                "isabelle-client-stream",
+                "echo \"$1\"; echo warning >&2; echo done; exit 3",
+            ),
+            ..Default::default()
+        };
+        let mut process = spawn_process(&args, None).unwrap();
+        let mut lines = Vec::new();
+        while let Some(line) = process.next_line().await {
+            lines.push((line.source, line.line));
+        }
+        lines.sort_by_key(|(source, _)| *source == OutputSource::Stderr);
+        assert_eq!(
+            lines,
+            vec![
+                (OutputSource::Stdout, "process".to_owned()),
+                (OutputSource::Stdout, "done".to_owned()),
+                (OutputSource::Stderr, "warning".to_owned()),
+            ]
+        );
+        assert_eq!(process.wait().await.unwrap().code(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_process_invalid_utf8() {
+        let args = ProcessArgs {
+            installation: fake_installation(
+                "isabelle-client-utf8",
+                "printf 'first\\n\\377\\nlast\\n'",
+            ),
+            ..Default::default()
+        };
+        let output = spawn_process(&args, None).unwrap().output().await.unwrap();
+        assert_eq!(output.text(OutputSource::Stdout), "first\n\u{fffd}\nlast");
+    }
+
+    #[tokio::test]
+    async fn test_ml_sources() {
+        let mut args = ProcessArgs::eval("1 + 1");
+        args.ml.push(MlSource::File("init.ML".to_owned()));
+        args.ml.push(MlSource::Expr("2 + 2".to_owned()));
+        args.modes.push("ASCII".to_owned());
+        args.installation = fake_installation("isabelle-client-ml", "echo \"$@\"");
+        let output = batch_process(&args, None).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "process -e 1 + 1 -f init.ML -e 2 + 2 -m ASCII\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_eval_ml() {
+        // Prints the text of `writeln` declarations, and `val it = EXPR` for other expressions
+        let script = r#"
+while [ $# -gt 0 ]; do
+  if [ "$1" = -e ]; then
+    case "$2" in
+      "val _ = writeln "*) echo "$2" | sed 's/^val _ = writeln "\(.*\)"$/\1/' ;;
+      *) echo "val it = $2" ;;
+    esac
+    shift
+  fi
+  shift
+done"#;
+        let mut args = ProcessArgs::eval("setup");
+        args.installation = fake_installation("isabelle-client-eval", script);
+        let value = eval_ml(&args, "1 + 1").await.unwrap();
+        assert_eq!(value, "val it = 1 + 1");
+        // The arguments are unchanged
+        assert_eq!(args.ml.len(), 1);
+
+        args.installation = fake_installation("isabelle-client-eval-error", "echo error; exit 1");
+        let err = eval_ml(&args, "x").await.unwrap_err();
+        assert!(err.to_string().contains("error"));
+    }
+
+    #[test]
+    fn test_between() {
+        let text = "noise\nBEGIN\nval it = 2: int\nEND\nmore";
+        assert_eq!(between(text, "BEGIN", "END").unwrap(), "val it = 2: int");
+        assert_eq!(between("BEGIN\nEND", "BEGIN", "END").unwrap(), "");
+        assert!(between("BEGIN\nval it = 2: int", "BEGIN", "END").is_none());
+        assert!(between("val it = 2: int\nEND", "BEGIN", "END").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_kill_process() {
+        let args = ProcessArgs {
+            installation: fake_installation("isabelle-client-kill", "echo started; sleep 30"),
+            ..Default::default()
+        };
+        let mut process = spawn_process(&args, None).unwrap();
+        assert_eq!(process.next_line().await.unwrap().line, "started");
+        process.kill().await.unwrap();
+        assert!(!process.wait().await.unwrap().success());
+    }
+
+    #[tokio::test]
+    async fn test_output_timeout() {
+        let args = ProcessArgs {
+            installation: fake_installation("isabelle-client-timeout", "echo started; sleep 30"),
+            timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let output = spawn_process(&args, None).unwrap().output().await.unwrap();
+        assert_eq!(output.status, BatchStatus::TimedOut);
+        assert_eq!(output.text(OutputSource::Stdout), "started");
+
+        let err = batch_process(&args, None).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn test_kill_handle() {
+        let args = ProcessArgs {
+            installation: fake_installation("isabelle-client-handle", "echo started; sleep 30"),
+            ..Default::default()
+        };
+        let mut process = spawn_process(&args, None).unwrap();
+        let handle = process.kill_handle();
+        assert_eq!(process.next_line().await.unwrap().line, "started");
+        tokio::spawn(async move { handle.kill().unwrap() });
+        let output = process.output().await.unwrap();
+        assert_eq!(output.status, BatchStatus::Killed);
+        assert!(!output.success());
+        // The line was already received
+        assert!(output.lines.is_empty());
+    }
+}
@@ -0,0 +1,1437 @@
+pub mod output;
+pub mod pool;
+pub mod registry;
+pub mod supervisor;
+
+use crate::client::{IsabelleClient, SyncResult};
+use crate::installation::Installation;
+use crate::limits::{self, GroupGuard, ResourceLimits};
+use output::{OutputSource, ServerOutput};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Time to wait for a server to terminate after a `shutdown` when it is dropped.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time within which the process of a reused server terminates after printing the address.
+const REUSE_GRACE: Duration = Duration::from_millis(500);
+
+/// Default number of output lines kept for a server.
+const OUTPUT_CAPACITY: usize = 1000;
+
+/// A running Isabelle server instance.
+///
+/// By default, the server is shut down when the instance is dropped:
+/// a `shutdown` command is sent to the server, and the server process is killed if it does not terminate within a few seconds.
+/// To leave the server running, use [IsabelleServer::set_shutdown_on_drop] or [ServerBuilder::shutdown_on_drop].
+/// If the instance is dropped within a tokio runtime, the shutdown happens in a background thread and the drop returns immediately;
+/// use [IsabelleServer::exit_async] to wait for the server to terminate.
+///
+/// Servers obtained by [IsabelleServer::attach] are not owned by the instance and are never shut down by it.
+pub struct IsabelleServer {
+    handle: Option<ServerProcess>,
+    port: u16,
+    passwd: String,
+    name: String,
+    installation: Installation,
+    shutdown_on_drop: bool,
+    /// Whether `exit` and drop may shut down the server
+    owned: bool,
+    output: ServerOutput,
+}
+
+impl IsabelleServer {
+    /// Attaches to the already running server `name` of the default installation, see [IsabelleServer::attach_with].
+    pub fn attach(name: &str) -> Result<Self, ServerError> {
+        Self::attach_with(&Installation::default(), name)
+    }
+
+    /// Attaches to the already running server `name` of `installation`, using the credentials from the servers registry.
+    /// Never starts a server, fails with [ServerError::NotRunning] if no server with that name is registered.
+    ///
+    /// The returned instance does not own the server: [IsabelleServer::exit] and dropping the instance leave it running.
+    /// Its output is not captured.
+    /// As the registry may contain stale entries, use [IsabelleServer::wait_ready] to check that the server is alive.
+    pub fn attach_with(installation: &Installation, name: &str) -> Result<Self, ServerError> {
+        let info = list_servers_with(installation)?
+            .into_iter()
+            .find(|s| s.name == name)
+            .ok_or_else(|| ServerError::NotRunning(name.to_owned()))?;
+        let output = ServerOutput::new(1);
+        output.close();
+        Ok(Self {
+            handle: None,
+            port: info.port,
+            passwd: info.password,
+            name: info.name,
+            installation: installation.clone(),
+            shutdown_on_drop: false,
+            owned: false,
+            output,
+        })
+    }
+
+    /// Returns whether this instance owns the server, i.e., whether it may shut it down.
+    pub fn is_owned(&self) -> bool {
+        self.owned
+    }
+
+    /// Returns the port of the running server instance.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the password of the running server instance.
+    pub fn password(&self) -> &str {
+        &self.passwd
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the address and credentials of the server.
+    pub fn info(&self) -> ServerInfo {
+        ServerInfo {
+            name: self.name.clone(),
+            host: "127.0.0.1".to_owned(),
+            port: self.port,
+            password: self.passwd.clone(),
+        }
+    }
+
+    /// Returns the Isabelle installation the server belongs to.
+    pub fn installation(&self) -> &Installation {
+        &self.installation
+    }
+
+    /// Returns the captured stdout, stderr, and log file output of the server.
+    /// Output is only captured if the server was started by this process.
+    pub fn output(&self) -> &ServerOutput {
+        &self.output
+    }
+
+    /// Sets whether the server is shut down when this instance is dropped.
+    /// If set to false, the server keeps running until [IsabelleServer::exit] or [exit] is called.
+    /// Has no effect if the server is not owned by this instance.
+    pub fn set_shutdown_on_drop(&mut self, flag: bool) {
+        self.shutdown_on_drop = flag && self.owned;
+    }
+
+    /// Kills the running server instance, if it was started by this process.
+    /// Does nothing if the server is not owned by this instance.
+    pub fn exit(&mut self) -> io::Result<()> {
+        self.shutdown_on_drop = false;
+        self.output.close();
+        if !self.owned {
+            log::debug!("Not exiting server {}, it is not owned", self.name);
+            return Ok(());
+        }
+        exit_with(&self.installation, &self.name)?;
+
+        // Also kill what remains of the process tree, such as Poly/ML processes
+        if let Some(mut handle) = self.handle.take() {
+            handle.kill_tree()?;
+        }
+
+        Ok(())
+    }
+
+    /// Asynchronous version of [IsabelleServer::exit].
+    pub async fn exit_async(&mut self) -> io::Result<()> {
+        self.shutdown_on_drop = false;
+        self.output.close();
+        if !self.owned {
+            log::debug!("Not exiting server {}, it is not owned", self.name);
+            return Ok(());
+        }
+        exit_async_with(&self.installation, &self.name).await?;
+
+        // Also kill what remains of the process tree, such as Poly/ML processes
+        if let Some(handle) = self.handle.take() {
+            handle.kill_tree_async().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits until the server accepts commands, i.e., a connection can be established,
+    /// the password handshake succeeds, and an `echo` command is answered.
+    /// Failed attempts are retried with increasing delays until `timeout` has elapsed.
+    ///
+    /// Fails with [ServerError::Stale] if the server was not started by this instance and refuses connections,
+    /// which happens if its registry entry is stale.
+    /// Fails with [ServerError::Exited] if the server process exits, and with [ServerError::TimedOut] if the server does not become ready in time.
+    pub async fn wait_ready(&mut self, timeout: Duration) -> Result<(), ServerError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut delay = Duration::from_millis(50);
+        loop {
+            let mut client = IsabelleClient::for_server(self);
+            let error = match tokio::time::timeout_at(deadline, client.echo("ready")).await {
+                Err(_) => return Err(ServerError::TimedOut(timeout)),
+                Ok(Ok(SyncResult::Ok(_))) => return Ok(()),
+                Ok(Ok(SyncResult::Error(e))) => io::Error::other(format!("echo failed: {}", e)),
+                Ok(Err(e)) => e,
+            };
+            log::debug!("Server {} is not ready: {}", self.name, error);
+
+            match self.handle.as_mut() {
+                Some(handle) => {
+                    if let Some(status) = handle.try_wait()? {
+                        let stderr = self.output.lines_of(OutputSource::Stderr).join("\n");
+                        return Err(ServerError::Exited { status, stderr });
+                    }
+                }
+                None if error.kind() == io::ErrorKind::ConnectionRefused => {
+                    return Err(ServerError::Stale {
+                        name: self.name.clone(),
+                        port: self.port,
+                    });
+                }
+                None => {}
+            }
+
+            if tokio::time::Instant::now() + delay >= deadline {
+                return Err(ServerError::TimedOut(timeout));
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(Duration::from_secs(1));
+        }
+    }
+
+    /// Checks whether the server process is still running without blocking.
+    /// Returns `None` if the process was not started by this instance,
+    /// in which case only a connection to [IsabelleServer::port] tells whether the server is alive.
+    pub(crate) fn process_alive(&mut self) -> Option<bool> {
+        self.handle
+            .as_mut()
+            .map(|handle| matches!(handle.try_wait(), Ok(None)))
+    }
+
+    /// Sends `shutdown` to the server and waits up to `timeout` for the server process to terminate, then kills it.
+    fn shutdown_blocking(&mut self, timeout: Duration) -> io::Result<()> {
+        if let Err(e) = IsabelleClient::for_server(self).shutdown_blocking(timeout) {
+            log::debug!("Could not send shutdown to server {}: {}", self.name, e);
+        }
+
+        if let Some(mut handle) = self.handle.take() {
+            let deadline = Instant::now() + timeout;
+            while Instant::now() < deadline {
+                if handle.try_wait()?.is_some() {
+                    // Kill remaining children of the server
+                    return handle.kill_tree();
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            log::warn!(
+                "Server {} did not terminate within {:?}, killing it",
+                self.name,
+                timeout
+            );
+            handle.kill_tree()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for IsabelleServer {
+    fn drop(&mut self) {
+        // Stop following the log file, also if the server keeps running
+        self.output.close();
+        if !self.shutdown_on_drop {
+            if let Some(ServerProcess::Async { child, .. }) = self.handle.take() {
+                // Leave the server running, dropping the child would kill it
+                std::mem::forget(child);
+            }
+            return;
+        }
+        self.shutdown_on_drop = false;
+        let mut server = IsabelleServer {
+            handle: self.handle.take(),
+            port: self.port,
+            passwd: std::mem::take(&mut self.passwd),
+            name: self.name.clone(),
+            installation: self.installation.clone(),
+            shutdown_on_drop: false,
+            owned: true,
+            output: self.output.clone(),
+        };
+        let mut shutdown = move || {
+            log::debug!("Shutting down server {}", server.name);
+            if let Err(e) = server.shutdown_blocking(SHUTDOWN_TIMEOUT) {
+                log::warn!("Could not shut down server {}: {}", server.name, e);
+            }
+        };
+        if tokio::runtime::Handle::try_current().is_ok() {
+            // Do not block the runtime while waiting for the server to terminate
+            std::thread::spawn(shutdown);
+        } else {
+            shutdown();
+        }
+    }
+}
+
+/// Builder to configure and run an Isabelle server (`isabelle server`).
+///
+/// # Example
+///
+/// ```rust
+/// use isabelle_client::server::ServerBuilder;
+/// let mut server = ServerBuilder::new()
+///     .name("test")
+///     .unique_name(true)
+///     .log_file("/tmp/isabelle-server.log")
+///     .run()
+///     .unwrap();
+/// assert!(server.port() > 0);
+/// server.exit().unwrap();
+/// ```
+#[derive(Default, Debug, Clone)]
+pub struct ServerBuilder {
+    name: Option<String>,
+    port: Option<u16>,
+    log_file: Option<PathBuf>,
+    existing_only: bool,
+    envs: HashMap<String, String>,
+    current_dir: Option<PathBuf>,
+    installation: Installation,
+    leave_running: bool,
+    output_capacity: Option<usize>,
+    limits: ResourceLimits,
+    isolated_home: bool,
+    shared_heaps: Option<PathBuf>,
+    unique_name: bool,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self {
+            ..Default::default()
+        }
+    }
+
+    /// Whether the server is shut down when the [IsabelleServer] is dropped (default is `true`).
+    pub fn shutdown_on_drop(&mut self, flag: bool) -> &mut Self {
+        self.leave_running = !flag;
+        self
+    }
+
+    /// The name of the server instance (`-n`, default is `isabelle`).
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// Explicit server port (`-p`).
+    pub fn port(&mut self, port: u16) -> &mut Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Log file for the server process (`-L`).
+    pub fn log_file<P: AsRef<Path>>(&mut self, file: P) -> &mut Self {
+        self.log_file = Some(file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Assume that the server is already running, i.e., do not start a new one if there is none (`-s`).
+    pub fn existing_only(&mut self, flag: bool) -> &mut Self {
+        self.existing_only = flag;
+        self
+    }
+
+    /// Sets an environment variable for the server process.
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.envs.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// The Isabelle installation to run the server with (default is `isabelle` from `PATH`).
+    pub fn installation(&mut self, installation: Installation) -> &mut Self {
+        self.installation = installation;
+        self
+    }
+
+    /// Sets the working directory of the server process.
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Number of lines of server output kept in [IsabelleServer::output] (default is 1000).
+    pub fn output_capacity(&mut self, lines: usize) -> &mut Self {
+        self.output_capacity = Some(lines);
+        self
+    }
+
+    /// Resource limits for the server process and its children.
+    /// The server runs in its own process group, which is killed as a whole when the server is killed.
+    ///
+    /// Avoid memory limits, the JVM of the server reserves much more virtual memory than it uses and fails to start under them.
+    pub fn limits(&mut self, limits: ResourceLimits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Runs the server against a private, temporary `ISABELLE_HOME_USER`, see [Installation::isolated].
+    /// A fresh home is created for each run and deleted when the [IsabelleServer] and all clones of its installation are dropped.
+    pub fn isolated_home(&mut self, flag: bool) -> &mut Self {
+        self.isolated_home = flag;
+        self
+    }
+
+    /// Session heaps shared with a server with an isolated home, in addition to the heaps of the installation, see [Installation::isolated].
+    /// Implies [ServerBuilder::isolated_home].
+    pub fn shared_heaps<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.isolated_home = true;
+        self.shared_heaps = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Appends a collision-free suffix to the name of each server started by this builder,
+    /// e.g., `test-4711-18c1b7f0a2e-0` for the name `test`, such that concurrent runs never share a server.
+    /// The generated name is available from [IsabelleServer::name].
+    pub fn unique_name(&mut self, flag: bool) -> &mut Self {
+        self.unique_name = flag;
+        self
+    }
+
+    /// Returns a copy of this builder for a single run, using a unique name and a fresh isolated installation if configured.
+    fn resolve(&self) -> io::Result<Option<ServerBuilder>> {
+        if !self.isolated_home && !self.unique_name {
+            return Ok(None);
+        }
+        let mut builder = self.clone();
+        builder.isolated_home = false;
+        builder.unique_name = false;
+        if self.unique_name {
+            builder.name = Some(unique_name(self.server_name()));
+        }
+        if self.isolated_home {
+            builder.installation = self.installation.isolated(self.shared_heaps.as_deref())?;
+        }
+        Ok(Some(builder))
+    }
+
+    /// Returns the name of the server instance.
+    fn server_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("isabelle")
+    }
+
+    /// Creates the `isabelle server` command for the configured options.
+    fn command(&self) -> Command {
+        let mut cmd = self.installation.command();
+        cmd.arg("server").arg("-n").arg(self.server_name());
+        if let Some(port) = self.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        if let Some(log_file) = &self.log_file {
+            cmd.arg("-L").arg(log_file);
+        }
+        if self.existing_only {
+            cmd.arg("-s");
+        }
+        if let Some(dir) = &self.current_dir {
+            cmd.current_dir(dir);
+        }
+        cmd.envs(&self.envs).stdout(Stdio::piped());
+        limits::isolate(&mut cmd, self.limits);
+        cmd
+    }
+
+    /// Returns the current length of the log file, such that only what the server appends after it has been started is followed.
+    fn log_offset(&self) -> u64 {
+        self.log_file
+            .as_ref()
+            .and_then(|f| f.metadata().ok())
+            .map_or(0, |m| m.len())
+    }
+
+    /// Creates the banner scanner for a spawned server and starts following its log file.
+    fn banner(&self, log_offset: u64) -> Banner {
+        let output = ServerOutput::new(self.output_capacity.unwrap_or(OUTPUT_CAPACITY));
+        if let Some(log_file) = &self.log_file {
+            output.follow(log_file.clone(), log_offset);
+        }
+        Banner {
+            skipped: Vec::new(),
+            output: Some(output),
+        }
+    }
+
+    /// Spawns the `isabelle server` process and starts capturing its stderr and log file.
+    /// Returns the thread capturing stderr along with the process.
+    fn spawn(&self) -> Result<(KillOnDrop, Banner, Option<JoinHandle<()>>), ServerError> {
+        let log_offset = self.log_offset();
+        let mut child = self
+            .command()
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(spawn_error)?;
+        let banner = self.banner(log_offset);
+        let stderr = match (child.stderr.take(), &banner.output) {
+            (Some(stderr), Some(output)) => Some(output.capture(OutputSource::Stderr, stderr)),
+            _ => None,
+        };
+        Ok((KillOnDrop(Some(child)), banner, stderr))
+    }
+
+    /// Asynchronous version of [ServerBuilder::spawn].
+    /// The process is killed when the returned child is dropped, the guard kills the rest of its process group.
+    #[allow(clippy::type_complexity)]
+    fn spawn_async(
+        &self,
+    ) -> Result<
+        (
+            tokio::process::Child,
+            GroupGuard,
+            Banner,
+            Option<tokio::task::JoinHandle<()>>,
+        ),
+        ServerError,
+    > {
+        let log_offset = self.log_offset();
+        let mut child = tokio::process::Command::from(self.command())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(spawn_error)?;
+        let group = GroupGuard::new(child.id());
+        let banner = self.banner(log_offset);
+        let stderr = match (child.stderr.take(), &banner.output) {
+            (Some(stderr), Some(output)) => {
+                Some(output.capture_async(OutputSource::Stderr, stderr))
+            }
+            _ => None,
+        };
+        Ok((child, group, banner, stderr))
+    }
+
+    /// Creates the [IsabelleServer] from the spawned process and the address it printed.
+    /// The server is owned if `handle` is given, i.e., if it is not a server that was already running.
+    fn server(
+        &self,
+        handle: Option<ServerProcess>,
+        banner: Banner,
+        info: ServerInfo,
+    ) -> IsabelleServer {
+        let output = banner.output.unwrap_or_else(|| ServerOutput::new(1));
+        let owned = handle.is_some();
+        IsabelleServer {
+            handle,
+            port: info.port,
+            passwd: info.password,
+            name: self.server_name().to_owned(),
+            installation: self.installation.clone(),
+            shutdown_on_drop: owned && !self.leave_running,
+            owned,
+            output,
+        }
+    }
+
+    /// Returns whether the server process terminates after printing the address,
+    /// which happens if a server with that name was already running.
+    fn reused(&self, child: &mut Child) -> io::Result<bool> {
+        if self.existing_only {
+            child.wait()?;
+            return Ok(true);
+        }
+        let deadline = Instant::now() + REUSE_GRACE;
+        loop {
+            if child.try_wait()?.is_some() {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Asynchronous version of [ServerBuilder::reused].
+    async fn reused_async(&self, child: &mut tokio::process::Child) -> io::Result<bool> {
+        if self.existing_only {
+            child.wait().await?;
+            return Ok(true);
+        }
+        match tokio::time::timeout(REUSE_GRACE, child.wait()).await {
+            Ok(status) => status.map(|_| true),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Runs the Isabelle server and returns an [IsabelleServer] instance containing port and password.
+    /// If a server is already running with the configured name, returns the port and password of that instance,
+    /// which is not owned by the returned instance, see [IsabelleServer::is_owned].
+    /// Otherwise, a new server is started, unless [ServerBuilder::existing_only] is set.
+    /// As a reused server is only recognised by its process terminating after printing the address,
+    /// starting a new server takes a fraction of a second longer than it prints its address.
+    ///
+    /// Output of the server preceding its address is skipped.
+    /// All output of the server, including its stderr and the log file, is available from [IsabelleServer::output].
+    /// Fails with a [ServerError] if the server could not be started or its address could not be determined,
+    /// in which case the server process is killed.
+    pub fn run(&self) -> Result<IsabelleServer, ServerError> {
+        match self.resolve()? {
+            Some(builder) => builder.start(),
+            None => self.start(),
+        }
+    }
+
+    /// Runs the server with the installation of this builder.
+    fn start(&self) -> Result<IsabelleServer, ServerError> {
+        let (mut guard, mut banner, stderr) = self.spawn()?;
+        let mut stdout = BufReader::new(guard.child().stdout.take().unwrap());
+
+        let mut buf = Vec::new();
+        while let Some(line) = output::read_line(&mut stdout, &mut buf)? {
+            if let Some(info) = banner.line(&line) {
+                let info = info?;
+                let mut child = guard.disarm();
+                let handle = match self.reused(&mut child)? {
+                    true => None,
+                    false => Some(ServerProcess::Sync(child)),
+                };
+                let server = self.server(handle, banner, info);
+                // Keep capturing what the server prints after its address
+                server.output.capture(OutputSource::Stdout, stdout);
+                return Ok(server);
+            }
+        }
+        let status = guard.disarm().wait();
+        // The stderr pipe is closed once the process has terminated
+        if let Some(thread) = stderr {
+            let _ = thread.join();
+        }
+        Err(banner.eof(status))
+    }
+
+    /// Asynchronous version of [ServerBuilder::run] that does not block the runtime while the server starts.
+    ///
+    /// Fails with [ServerError::TimedOut] if the server does not print its address within `timeout`.
+    /// If the startup fails or the future is dropped before the server has started, the server process is killed.
+    pub async fn run_async(&self, timeout: Duration) -> Result<IsabelleServer, ServerError> {
+        let resolved = if self.isolated_home {
+            // Creating the isolated home runs `isabelle getenv`
+            let builder = self.clone();
+            tokio::task::spawn_blocking(move || builder.resolve())
+                .await
+                .map_err(io::Error::other)??
+        } else {
+            self.resolve()?
+        };
+        match resolved {
+            Some(builder) => builder.start_async(timeout).await,
+            None => self.start_async(timeout).await,
+        }
+    }
+
+    /// Asynchronously runs the server with the installation of this builder.
+    async fn start_async(&self, timeout: Duration) -> Result<IsabelleServer, ServerError> {
+        let (mut child, mut group, mut banner, stderr) = self.spawn_async()?;
+        let mut stdout = tokio::io::BufReader::new(child.stdout.take().unwrap());
+
+        let scan = async {
+            let mut buf = Vec::new();
+            while let Some(line) = output::read_line_async(&mut stdout, &mut buf).await? {
+                if let Some(info) = banner.line(&line) {
+                    return Ok(Some(info?));
+                }
+            }
+            Ok::<_, ServerError>(None)
+        };
+        match tokio::time::timeout(timeout, scan).await {
+            Err(_) => Err(ServerError::TimedOut(timeout)),
+            Ok(Err(e)) => Err(e),
+            Ok(Ok(Some(info))) => {
+                let handle = match self.reused_async(&mut child).await? {
+                    true => None,
+                    false => Some(ServerProcess::Async {
+                        pid: child.id(),
+                        child,
+                    }),
+                };
+                group.disarm();
+                let server = self.server(handle, banner, info);
+                // Keep capturing what the server prints after its address
+                server.output.capture_async(OutputSource::Stdout, stdout);
+                Ok(server)
+            }
+            Ok(Ok(None)) => {
+                let status = child.wait().await;
+                // The stderr pipe is closed once the process has terminated
+                if let Some(task) = stderr {
+                    let _ = task.await;
+                }
+                Err(banner.eof(status))
+            }
+        }
+    }
+}
+
+/// Returns `base` with a suffix that is unique across processes and time.
+pub(crate) fn unique_name(base: &str) -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    format!(
+        "{}-{}-{:x}-{}",
+        base,
+        std::process::id(),
+        nanos,
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Scans the output of a starting server for the line containing its address.
+#[derive(Default)]
+struct Banner {
+    /// Unrelated output preceding the address
+    skipped: Vec<String>,
+    /// Captured output of the server
+    output: Option<ServerOutput>,
+}
+
+impl Banner {
+    /// Processes a line of output. Returns the server info once the address line appears.
+    fn line(&mut self, line: &str) -> Option<Result<ServerInfo, ServerError>> {
+        let line = line.trim();
+        if line.starts_with("server \"") {
+            return Some(line.parse());
+        }
+        if !line.is_empty() {
+            log::debug!("Skipping server output: {}", line);
+            if let Some(output) = &self.output {
+                output.push(OutputSource::Stdout, line);
+            }
+            self.skipped.push(line.to_owned());
+        }
+        None
+    }
+
+    /// Returns the error for a server process whose output ended before it printed its address,
+    /// given the exit status of the process.
+    fn eof(self, status: io::Result<ExitStatus>) -> ServerError {
+        let stderr = match &self.output {
+            Some(output) => {
+                output.close();
+                output.lines_of(OutputSource::Stderr).join("\n")
+            }
+            None => String::new(),
+        };
+        match status {
+            Ok(status) if !status.success() => ServerError::Exited { status, stderr },
+            Ok(_) => ServerError::UnrecognisedBanner(self.skipped.join("\n")),
+            Err(e) => ServerError::Io(e),
+        }
+    }
+}
+
+/// Errors that occur while starting an Isabelle server.
+#[derive(Debug)]
+pub enum ServerError {
+    /// The `isabelle` executable could not be found
+    NotFound(io::Error),
+    /// The server process exited before printing its address
+    Exited {
+        /// Exit status of the process
+        status: ExitStatus,
+        /// Everything the process printed to stderr
+        stderr: String,
+    },
+    /// The server printed output that does not contain a recognisable address, contains the raw output
+    UnrecognisedBanner(String),
+    /// The server printed an address with an invalid port
+    BadPort(String),
+    /// The server did not print its address or become ready in time
+    TimedOut(Duration),
+    /// No server with the given name is running
+    NotRunning(String),
+    /// The server is registered, but refuses connections on its port
+    Stale {
+        /// Name of the server
+        name: String,
+        /// Port recorded for the server
+        port: u16,
+    },
+    /// Communication with the server process failed
+    Io(io::Error),
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerError::NotFound(e) => write!(f, "Isabelle executable not found: {}", e),
+            ServerError::Exited { status, stderr } => {
+                write!(f, "Server exited with {}: {}", status, stderr.trim())
+            }
+            ServerError::UnrecognisedBanner(raw) => {
+                write!(f, "Unrecognised server output: {}", raw)
+            }
+            ServerError::BadPort(port) => write!(f, "Invalid server port: {}", port),
+            ServerError::TimedOut(timeout) => {
+                write!(f, "Server did not start within {:?}", timeout)
+            }
+            ServerError::NotRunning(name) => write!(f, "No server named {} is running", name),
+            ServerError::Stale { name, port } => write!(
+                f,
+                "Server {} is registered on port {}, but refuses connections (stale registry entry)",
+                name, port
+            ),
+            ServerError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServerError::NotFound(e) | ServerError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> Self {
+        ServerError::Io(e)
+    }
+}
+
+impl From<ServerError> for io::Error {
+    fn from(e: ServerError) -> Self {
+        match e {
+            ServerError::NotFound(e) | ServerError::Io(e) => e,
+            ServerError::TimedOut(_) => io::Error::new(io::ErrorKind::TimedOut, e.to_string()),
+            ServerError::UnrecognisedBanner(_) | ServerError::BadPort(_) => {
+                io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+            }
+            ServerError::Exited { .. } => io::Error::other(e.to_string()),
+            ServerError::NotRunning(_) => io::Error::new(io::ErrorKind::NotFound, e.to_string()),
+            ServerError::Stale { .. } => {
+                io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string())
+            }
+        }
+    }
+}
+
+/// Maps an error spawning the server process to a [ServerError].
+fn spawn_error(e: io::Error) -> ServerError {
+    match e.kind() {
+        io::ErrorKind::NotFound => ServerError::NotFound(e),
+        _ => ServerError::Io(e),
+    }
+}
+
+/// The process of a server started by this instance, spawned by [ServerBuilder::run] or [ServerBuilder::run_async].
+enum ServerProcess {
+    Sync(Child),
+    /// Killed when dropped
+    Async {
+        child: tokio::process::Child,
+        /// Id of the process, which the child forgets once it has been reaped
+        pid: Option<u32>,
+    },
+}
+
+impl ServerProcess {
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match self {
+            ServerProcess::Sync(child) => child.try_wait(),
+            ServerProcess::Async { child, .. } => child.try_wait(),
+        }
+    }
+
+    /// Kills the process along with its process group, also if the process itself has already terminated.
+    /// Only waits for a process spawned synchronously, asynchronous processes are reaped by the runtime.
+    fn kill_tree(&mut self) -> io::Result<()> {
+        match self {
+            ServerProcess::Sync(child) => limits::kill_tree(child),
+            ServerProcess::Async { child, pid } => {
+                if let Some(pid) = pid {
+                    limits::kill_group(*pid)?;
+                }
+                match child.id() {
+                    Some(_) => child.start_kill(),
+                    // Already terminated and reaped
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Asynchronous version of [ServerProcess::kill_tree] that waits for the process to terminate.
+    async fn kill_tree_async(self) -> io::Result<()> {
+        match self {
+            ServerProcess::Sync(mut child) => {
+                tokio::task::spawn_blocking(move || limits::kill_tree(&mut child)).await?
+            }
+            ServerProcess::Async { mut child, pid } => {
+                if let Some(pid) = pid {
+                    limits::kill_group(pid)?;
+                }
+                match child.id() {
+                    Some(_) => child.kill().await,
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// Kills the wrapped child process when dropped, unless it was disarmed.
+struct KillOnDrop(Option<Child>);
+
+impl KillOnDrop {
+    fn child(&mut self) -> &mut Child {
+        self.0.as_mut().expect("Child already disarmed")
+    }
+
+    fn disarm(&mut self) -> Child {
+        self.0.take().expect("Child already disarmed")
+    }
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.0.take() {
+            log::debug!("Killing server process {}", child.id());
+            if let Err(e) = limits::kill_tree(&mut child) {
+                log::warn!("Could not kill server process {}: {}", child.id(), e);
+            }
+        }
+    }
+}
+
+/// Runs the Isabelle server and returns an [IsabelleServer] instance containing port and password.
+/// If a server is already running with the given name, the function returns the port and password of the instance.
+/// If no server is running with the given name, the function starts a new server.
+/// The server is shut down when the returned [IsabelleServer] is dropped.
+/// To leave it running, call [IsabelleServer::set_shutdown_on_drop], and stop it later using [IsabelleServer::exit] or [exit].
+///
+/// Use [ServerBuilder] to configure further options of the server.
+///
+/// # Arguments
+///
+/// - `name` - The name of the server instance (default is `isabelle`).
+///
+/// # Returns
+///
+/// An [IsabelleServer] instance containing name, port, and password.
+///
+///
+/// # Example
+///
+/// ```rust
+/// use isabelle_client::server::run_server;
+/// let mut server = run_server(Some("test")).unwrap();
+/// assert!(server.port() > 0);
+/// assert!(!server.password().is_empty());
+/// server.exit();
+/// ```
+pub fn run_server(name: Option<&str>) -> Result<IsabelleServer, ServerError> {
+    let mut builder = ServerBuilder::new();
+    if let Some(name) = name {
+        builder.name(name);
+    }
+    builder.run()
+}
+
+/// Registry entry of a running Isabelle server, as listed by `isabelle server -l`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// The name of the server instance
+    pub name: String,
+    /// The host the server listens on
+    pub host: String,
+    /// The port the server listens on
+    pub port: u16,
+    /// The password of the server
+    pub password: String,
+}
+
+impl ServerInfo {
+    /// Returns a client connected to this server.
+    pub fn client(&self) -> IsabelleClient {
+        IsabelleClient::connect(Some(&self.host), self.port, &self.password)
+    }
+}
+
+impl FromStr for ServerInfo {
+    type Err = ServerError;
+
+    /// Parses a line of the form `server "NAME" = HOST:PORT (password "PASSWORD")`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = s.replace('\\', "");
+        let line = line.trim();
+        let addr_re = regex::Regex::new(r#"^server "(.*)" = (.*):(.*) \(password "(.*)"\)$"#)
+            .expect("Invalid regex");
+        let caps = addr_re
+            .captures(line)
+            .ok_or_else(|| ServerError::UnrecognisedBanner(line.to_owned()))?;
+        let port = caps[3]
+            .parse::<u16>()
+            .map_err(|_| ServerError::BadPort(caps[3].to_owned()))?;
+        Ok(Self {
+            name: caps[1].to_owned(),
+            host: caps[2].to_owned(),
+            port,
+            password: caps[4].to_owned(),
+        })
+    }
+}
+
+/// Lists the running Isabelle servers (`isabelle server -l`).
+///
+/// # Example
+///
+/// ```rust
+/// use isabelle_client::server::{list_servers, run_server};
+/// let mut server = run_server(Some("test")).unwrap();
+/// let servers = list_servers().unwrap();
+/// assert!(servers.iter().any(|s| s.name == "test"));
+/// server.exit();
+/// ```
+pub fn list_servers() -> io::Result<Vec<ServerInfo>> {
+    list_servers_with(&Installation::default())
+}
+
+/// Lists the running Isabelle servers of the given installation (`isabelle server -l`).
+pub fn list_servers_with(installation: &Installation) -> io::Result<Vec<ServerInfo>> {
+    let output = installation
+        .command()
+        .arg("server")
+        .arg("-l")
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "isabelle server -l failed with {}",
+            output.status
+        )));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.parse().map_err(io::Error::from))
+        .collect()
+}
+
+/// Asynchronous version of [run_server] that does not block the runtime while the server starts.
+/// Fails if the server does not start within `timeout`, see [ServerBuilder::run_async].
+///
+/// # Example
+///
+/// ```rust
+/// use isabelle_client::server::run_server_async;
+/// use std::time::Duration;
+/// # tokio_test::block_on(async {
+/// let mut server = run_server_async(Some("test"), Duration::from_secs(60)).await.unwrap();
+/// assert!(server.port() > 0);
+/// server.exit_async().await.unwrap();
+/// # })
+/// ```
+pub async fn run_server_async(
+    name: Option<&str>,
+    timeout: Duration,
+) -> Result<IsabelleServer, ServerError> {
+    let mut builder = ServerBuilder::new();
+    if let Some(name) = name {
+        builder.name(name);
+    }
+    builder.run_async(timeout).await
+}
+
+/// Exists the Isabelle server with the given name.
+pub fn exit(name: &str) -> io::Result<ExitStatus> {
+    exit_with(&Installation::default(), name)
+}
+
+/// Exists the Isabelle server with the given name of the given installation.
+pub fn exit_with(installation: &Installation, name: &str) -> io::Result<ExitStatus> {
+    let mut child = installation
+        .command()
+        .arg("server")
+        .arg("-n")
+        .arg(name)
+        .arg("-x")
+        .spawn()?;
+    child.wait()
+}
+
+/// Asynchronous version of [exit].
+pub async fn exit_async(name: &str) -> io::Result<ExitStatus> {
+    exit_async_with(&Installation::default(), name).await
+}
+
+/// Asynchronous version of [exit_with].
+pub async fn exit_async_with(installation: &Installation, name: &str) -> io::Result<ExitStatus> {
+    installation
+        .tokio_command()
+        .arg("server")
+        .arg("-n")
+        .arg(name)
+        .arg("-x")
+        .status()
+        .await
+}
+
+mod tests {
+
+    #![allow(unused_imports)] // rust-analyzer thinks these are unused, but are not
+    use super::{
+        list_servers, list_servers_with, run_server, run_server_async, unique_name, Banner,
+        Installation, IsabelleServer, ServerBuilder, ServerError, ServerInfo, ServerOutput,
+    };
+    #[cfg(all(test, unix))]
+    use crate::test_util::fake_installation;
+    use serial_test::serial;
+    use std::time::Duration;
+
+    #[test]
+    #[serial]
+    fn test_run_server() {
+        let mut server = run_server(Some(&unique_name("test"))).unwrap();
+        assert!(server.port > 0);
+        assert!(!server.passwd.is_empty());
+        server.exit().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_server_with_builder() {
+        let log_file =
+            std::env::temp_dir().join(unique_name("isabelle-client-test-server") + ".log");
+        let mut server = ServerBuilder::new()
+            .name("test")
+            .unique_name(true)
+            .log_file(&log_file)
+            .run()
+            .unwrap();
+        assert!(server.port() > 0);
+        assert!(log_file.exists());
+        server.exit().unwrap();
+        let _ = std::fs::remove_file(&log_file);
+    }
+
+    #[test]
+    fn test_parse_server_info() {
+        let info: ServerInfo = r#"server "test" = 127.0.0.1:4711 (password "7d2b6c3e-1f0a")"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            info,
+            ServerInfo {
+                name: "test".to_owned(),
+                host: "127.0.0.1".to_owned(),
+                port: 4711,
+                password: "7d2b6c3e-1f0a".to_owned(),
+            }
+        );
+        assert!("Welcome to Isabelle".parse::<ServerInfo>().is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_list_servers() {
+        let mut server = run_server(Some(&unique_name("test"))).unwrap();
+        let servers = list_servers().unwrap();
+        let info = servers.iter().find(|s| s.name == server.name()).unwrap();
+        assert_eq!(info.port, server.port());
+        assert_eq!(info.password, server.password());
+        server.exit().unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_run_server_async() {
+        let mut server = run_server_async(
+            Some(&unique_name("test")),
+            std::time::Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+        assert!(server.port > 0);
+        assert!(!server.passwd.is_empty());
+        server.exit_async().await.unwrap();
+    }
+
+    /// Creates an instance for a server on `port` that was not started by this process.
+    #[cfg(test)]
+    fn unowned_server(port: u16) -> IsabelleServer {
+        IsabelleServer {
+            handle: None,
+            port,
+            passwd: "pass".to_owned(),
+            name: "test".to_owned(),
+            installation: Installation::default(),
+            shutdown_on_drop: false,
+            owned: false,
+            output: ServerOutput::new(1),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_attach() {
+        // Fake installation with a registered server that records attempts to exit it
+        let installation = fake_installation(
+            "isabelle-client-attach",
+            concat!(
+                "case \"$2\" in\n",
+                "  -l) echo 'server \"other\" = 127.0.0.1:4711 (password \"secret\")' ;;\n",
+                "  -x) touch \"$(dirname \"$0\")/exited\" ;;\n",
+                "esac",
+            ),
+        );
+        let dir = installation.executable().parent().unwrap().to_path_buf();
+
+        let mut server = IsabelleServer::attach_with(&installation, "other").unwrap();
+        assert!(!server.is_owned());
+        assert_eq!(server.port(), 4711);
+        assert_eq!(server.password(), "secret");
+        server.set_shutdown_on_drop(true);
+        server.exit().unwrap();
+        drop(server);
+        assert!(!dir.join("exited").exists());
+
+        let res = IsabelleServer::attach_with(&installation, "missing");
+        assert!(matches!(res, Err(ServerError::NotRunning(name)) if name == "missing"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_async_kills_server() {
+        let installation = fake_installation(
+            "isabelle-client-run-async",
+            concat!(
+                "echo 'server \"fake\" = 127.0.0.1:4711 (password \"secret\")'\n",
+                "echo started >&2\n",
+                "exec sleep 30",
+            ),
+        );
+        let mut server = ServerBuilder::new()
+            .name("fake")
+            .installation(installation)
+            .run_async(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(server.port(), 4711);
+        assert_eq!(server.process_alive(), Some(true));
+
+        server
+            .handle
+            .take()
+            .unwrap()
+            .kill_tree_async()
+            .await
+            .unwrap();
+        assert!(server.handle.is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_run_async_timeout_kills_server() {
+        let installation = fake_installation(
+            "isabelle-client-run-async-timeout",
+            "echo $$ > \"$(dirname \"$0\")/pid\"\nexec sleep 30",
+        );
+        let pid_file = installation.executable().parent().unwrap().join("pid");
+        let res = ServerBuilder::new()
+            .installation(installation)
+            .run_async(Duration::from_millis(500))
+            .await;
+        assert!(matches!(res, Err(ServerError::TimedOut(_))));
+
+        // The process is gone or a zombie waiting to be reaped
+        let pid = std::fs::read_to_string(pid_file).unwrap();
+        let dead = || {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+            stat.map_or(true, |s| s.contains(") Z "))
+        };
+        for _ in 0..50 {
+            if dead() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(dead());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reused_server_is_not_owned() {
+        // The first run starts a server, later runs print its address and exit
+        let installation = fake_installation(
+            "isabelle-client-reuse",
+            concat!(
+                "case \"$*\" in *-x) exit 0 ;; esac\n",
+                "echo 'server \"fake\" = 127.0.0.1:4711 (password \"secret\")'\n",
+                "running=\"$(dirname \"$0\")/running\"\n",
+                "[ -e \"$running\" ] && exit 0\n",
+                "touch \"$running\"\n",
+                "exec sleep 30",
+            ),
+        );
+        let mut builder = ServerBuilder::new();
+        builder.name("fake").installation(installation);
+        let mut first = builder.run().unwrap();
+        assert!(first.is_owned());
+
+        let second = builder.run().unwrap();
+        assert!(!second.is_owned());
+        assert_eq!(second.port(), 4711);
+        drop(second);
+        assert_eq!(first.process_alive(), Some(true));
+
+        first.exit().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_drop_does_not_block_runtime() {
+        // The server refuses the shutdown command and ignores it
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let installation = fake_installation(
+            "isabelle-client-drop",
+            &format!(
+                "echo 'server \"fake\" = 127.0.0.1:{} (password \"secret\")'\nexec sleep 30",
+                port
+            ),
+        );
+        let server = ServerBuilder::new()
+            .name("fake")
+            .installation(installation)
+            .run_async(Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        let start = std::time::Instant::now();
+        drop(server);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_drop_closes_output() {
+        let server = unowned_server(4711);
+        let output = server.output().clone();
+        drop(server);
+        assert!(output.tail().next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wait_ready() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut server = unowned_server(listener.local_addr().unwrap().port());
+        tokio::spawn(async move {
+            let (con, _) = listener.accept().await.unwrap();
+            let (r, mut w) = con.into_split();
+            let mut lines = BufReader::new(r).lines();
+            assert_eq!(lines.next_line().await.unwrap().unwrap(), "pass");
+            w.write_all(b"OK\n").await.unwrap();
+            assert_eq!(lines.next_line().await.unwrap().unwrap(), r#"echo "ready""#);
+            w.write_all(b"OK \"ready\"\n").await.unwrap();
+        });
+
+        server.wait_ready(Duration::from_secs(5)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wait_ready_stale() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let err = unowned_server(port)
+            .wait_ready(Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ServerError::Stale { port: p, .. } if p == port));
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let mut builder = ServerBuilder::new();
+        builder.name("test").unique_name(true);
+        let first = builder.resolve().unwrap().unwrap();
+        let second = builder.resolve().unwrap().unwrap();
+        assert!(first.server_name().starts_with("test-"));
+        assert_ne!(first.server_name(), second.server_name());
+        assert!(ServerBuilder::new().resolve().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_banner_skips_unrelated_output() {
+        let mut banner = Banner::default();
+        assert!(banner.line("### Warning: something").is_none());
+        assert!(banner.line("").is_none());
+        let info = banner
+            .line(r#"server "test" = 127.0.0.1:4711 (password "secret")"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.port, 4711);
+        assert_eq!(banner.skipped, vec!["### Warning: something".to_owned()]);
+    }
+
+    #[test]
+    fn test_banner_bad_port() {
+        let mut banner = Banner::default();
+        let res = banner
+            .line(r#"server "test" = 127.0.0.1:99999999999 (password "secret")"#)
+            .unwrap();
+        assert!(matches!(res, Err(ServerError::BadPort(p)) if p == "99999999999"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_existing_only_without_server() {
+        let res = ServerBuilder::new()
+            .name(&unique_name("test-not-running"))
+            .existing_only(true)
+            .run();
+        assert!(matches!(res, Err(ServerError::Exited { .. })));
+    }
+
+    #[test]
+    fn test_isolated_home() {
+        let mut server = ServerBuilder::new()
+            .name("isolated")
+            .isolated_home(true)
+            .run()
+            .unwrap();
+        let home = server.installation().isolated_home().unwrap().to_path_buf();
+        assert!(home.join("servers.db").exists());
+
+        let servers = list_servers_with(server.installation()).unwrap();
+        assert!(servers.iter().any(|s| s.name == "isolated"));
+        server.exit().unwrap();
+        drop(server);
+        assert!(!home.exists());
+    }
+
+    #[test]
+    #[serial]
+    fn test_shutdown_on_drop() {
+        let name = unique_name("test");
+        let server = run_server(Some(&name)).unwrap();
+        drop(server);
+        let servers = list_servers().unwrap();
+        assert!(servers.iter().all(|s| s.name != name));
+    }
+}
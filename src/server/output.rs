@@ -0,0 +1,339 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead},
+    sync::broadcast,
+};
+
+/// Interval in which the server log file is checked for new lines.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The stream a line of process output originates from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputSource {
+    Stdout,
+    Stderr,
+    /// The server log file (`-L`)
+    LogFile,
+}
+
+/// A line of output of a server or batch process.
+#[derive(Debug, Clone)]
+pub struct OutputLine {
+    pub source: OutputSource,
+    pub line: String,
+    /// Time the line was captured
+    pub time: SystemTime,
+}
+
+struct OutputInner {
+    lines: Mutex<VecDeque<OutputLine>>,
+    capacity: usize,
+    /// Sender for the tails, taken when the output is closed
+    tx: Mutex<Option<broadcast::Sender<OutputLine>>>,
+    closed: AtomicBool,
+}
+
+/// Captured output of an Isabelle server: its stdout, its stderr, and its log file if one is configured.
+///
+/// The most recent lines are kept in a bounded ring buffer, which can be inspected using [ServerOutput::lines].
+/// Use [ServerOutput::tail] to follow new lines as they arrive.
+#[derive(Clone)]
+pub struct ServerOutput {
+    inner: Arc<OutputInner>,
+}
+
+impl ServerOutput {
+    /// Creates an empty output buffer keeping at most `capacity` lines.
+    pub(crate) fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (tx, _) = broadcast::channel(capacity);
+        Self {
+            inner: Arc::new(OutputInner {
+                lines: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity,
+                tx: Mutex::new(Some(tx)),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Returns the buffered lines, oldest first.
+    pub fn lines(&self) -> Vec<OutputLine> {
+        self.inner.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Returns the buffered lines of the given source, oldest first.
+    pub fn lines_of(&self, source: OutputSource) -> Vec<String> {
+        let lines = self.inner.lines.lock().unwrap();
+        lines
+            .iter()
+            .filter(|l| l.source == source)
+            .map(|l| l.line.clone())
+            .collect()
+    }
+
+    /// Returns a stream of the lines captured from now on.
+    /// If the output is already closed, the stream ends immediately.
+    pub fn tail(&self) -> OutputTail {
+        let rx = match self.inner.tx.lock().unwrap().as_ref() {
+            Some(tx) => tx.subscribe(),
+            None => broadcast::channel(1).1,
+        };
+        OutputTail { rx }
+    }
+
+    /// Appends a line to the buffer and notifies all tails.
+    pub(crate) fn push(&self, source: OutputSource, line: &str) {
+        let line = OutputLine {
+            source,
+            line: line.to_owned(),
+            time: SystemTime::now(),
+        };
+        {
+            let mut lines = self.inner.lines.lock().unwrap();
+            if lines.len() == self.inner.capacity {
+                lines.pop_front();
+            }
+            lines.push_back(line.clone());
+        }
+        if let Some(tx) = self.inner.tx.lock().unwrap().as_ref() {
+            // There may be no tails, the line is only buffered in that case
+            let _ = tx.send(line);
+        }
+    }
+
+    /// Captures the lines read from `reader` in a background thread until it is closed.
+    pub(crate) fn capture<R: Read + Send + 'static>(
+        &self,
+        source: OutputSource,
+        reader: R,
+    ) -> JoinHandle<()> {
+        let output = self.clone();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut buf = Vec::new();
+            loop {
+                match read_line(&mut reader, &mut buf) {
+                    Ok(Some(line)) => output.push(source, &line),
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::debug!("Could not read {:?}: {}", source, e);
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Asynchronous version of [ServerOutput::capture] that captures the lines in a task.
+    pub(crate) fn capture_async<R: AsyncRead + Unpin + Send + 'static>(
+        &self,
+        source: OutputSource,
+        reader: R,
+    ) -> tokio::task::JoinHandle<()> {
+        let output = self.clone();
+        tokio::spawn(async move {
+            let mut reader = tokio::io::BufReader::new(reader);
+            let mut buf = Vec::new();
+            loop {
+                match read_line_async(&mut reader, &mut buf).await {
+                    Ok(Some(line)) => output.push(source, &line),
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::debug!("Could not read {:?}: {}", source, e);
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Captures the lines appended to the file at `path` after `offset` in a background thread,
+    /// until the output is closed.
+    pub(crate) fn follow(&self, path: PathBuf, offset: u64) {
+        let output = self.clone();
+        std::thread::spawn(move || {
+            let mut reader = None;
+            let mut line = Vec::new();
+            while !output.inner.closed.load(Ordering::SeqCst) {
+                if reader.is_none() {
+                    // The server may not have created the file yet
+                    reader = File::open(&path).ok().and_then(|mut f| {
+                        f.seek(SeekFrom::Start(offset)).ok()?;
+                        Some(BufReader::new(f))
+                    });
+                }
+                if let Some(r) = reader.as_mut() {
+                    loop {
+                        match r.read_until(b'\n', &mut line) {
+                            // Only complete lines are captured, partial lines are continued later
+                            Ok(n) if n > 0 && line.ends_with(b"\n") => {
+                                output.push(
+                                    OutputSource::LogFile,
+                                    String::from_utf8_lossy(&line).trim_end(),
+                                );
+                                line.clear();
+                            }
+                            Ok(_) => break,
+                            Err(e) => {
+                                log::warn!("Could not read {}: {}", path.display(), e);
+                                return;
+                            }
+                        }
+                    }
+                }
+                std::thread::sleep(FOLLOW_INTERVAL);
+            }
+        });
+    }
+
+    /// Stops following the log file and ends all tails once the buffered lines are consumed.
+    pub(crate) fn close(&self) {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        self.inner.tx.lock().unwrap().take();
+    }
+}
+
+/// Reads a line from `reader` into `buf`, replacing invalid UTF-8 instead of failing on it.
+/// Returns the line without its line terminator, or `None` at the end of the input.
+pub(crate) fn read_line<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+) -> io::Result<Option<String>> {
+    buf.clear();
+    if reader.read_until(b'\n', buf)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(decode_line(buf)))
+}
+
+/// Asynchronous version of [read_line].
+pub(crate) async fn read_line_async<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+) -> io::Result<Option<String>> {
+    buf.clear();
+    if reader.read_until(b'\n', buf).await? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(decode_line(buf)))
+}
+
+fn decode_line(buf: &[u8]) -> String {
+    let line = buf.strip_suffix(b"\n").unwrap_or(buf);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    String::from_utf8_lossy(line).into_owned()
+}
+
+/// Stream of new lines of a [ServerOutput].
+pub struct OutputTail {
+    rx: broadcast::Receiver<OutputLine>,
+}
+
+impl OutputTail {
+    /// Waits for the next line.
+    /// Returns `None` once the server has been shut down and all lines have been consumed.
+    ///
+    /// If the tail falls behind by more than the capacity of the buffer, the oldest lines are skipped.
+    pub async fn next(&mut self) -> Option<OutputLine> {
+        loop {
+            match self.rx.recv().await {
+                Ok(line) => return Some(line),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    log::debug!("Output tail skipped {} lines", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer_keeps_latest_lines() {
+        let output = ServerOutput::new(2);
+        output.push(OutputSource::Stdout, "a");
+        output.push(OutputSource::Stderr, "b");
+        output.push(OutputSource::Stdout, "c");
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].line, "b");
+        assert_eq!(lines[1].line, "c");
+        assert_eq!(output.lines_of(OutputSource::Stdout), vec!["c".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn test_tail_receives_new_lines() {
+        let output = ServerOutput::new(10);
+        output.push(OutputSource::Stdout, "before");
+        let mut tail = output.tail();
+        output.capture(OutputSource::Stderr, &b"first\nsecond\n"[..]);
+
+        let line = tail.next().await.unwrap();
+        assert_eq!(line.source, OutputSource::Stderr);
+        assert_eq!(line.line, "first");
+        assert_eq!(tail.next().await.unwrap().line, "second");
+
+        output.close();
+        assert!(tail.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_follow_log_file() {
+        let path = std::env::temp_dir()
+            .join(crate::server::unique_name("isabelle-client-follow") + ".log");
+        std::fs::write(&path, "old\n").unwrap();
+        let output = ServerOutput::new(10);
+        let mut tail = output.tail();
+        output.follow(path.clone(), 4);
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"new\n").unwrap();
+
+        let line = tail.next().await.unwrap();
+        assert_eq!(line.source, OutputSource::LogFile);
+        assert_eq!(line.line, "new");
+        output.close();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_capture_invalid_utf8() {
+        let output = ServerOutput::new(10);
+        output
+            .capture(OutputSource::Stdout, &b"first\n\xff\r\nlast\n"[..])
+            .join()
+            .unwrap();
+        output
+            .capture_async(OutputSource::Stderr, &b"first\n\xff\nlast"[..])
+            .await
+            .unwrap();
+        assert_eq!(
+            output.lines_of(OutputSource::Stdout),
+            vec!["first", "\u{fffd}", "last"]
+        );
+        assert_eq!(
+            output.lines_of(OutputSource::Stderr),
+            vec!["first", "\u{fffd}", "last"]
+        );
+    }
+}
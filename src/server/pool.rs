@@ -0,0 +1,364 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::{IsabelleServer, ServerBuilder, ServerError, ServerInfo};
+use crate::client::{
+    args::{SessionStartArgs, UseTheoriesArgs},
+    results::{PurgeTheoryResults, SessionStopResult, UseTheoryResults},
+    session::Session,
+    AsyncResult, SyncResult,
+};
+
+/// Configuration of a [ServerPool].
+#[derive(Debug, Clone)]
+pub struct ServerPoolConfig {
+    /// Number of server processes.
+    pub size: usize,
+    /// Time a server may take to print its address after it has been started.
+    pub startup_timeout: Duration,
+    /// Isabelle system options added to every session started in the pool, e.g., the number of threads of a session.
+    /// The options apply to each session individually, i.e., `threads` limits the threads of a session, not of a server.
+    /// Use [crate::process::OptionsBuilder] to construct options.
+    pub session_options: HashMap<String, String>,
+}
+
+impl Default for ServerPoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 2,
+            startup_timeout: Duration::from_secs(60),
+            session_options: HashMap::new(),
+        }
+    }
+}
+
+struct PoolServer {
+    server: Mutex<IsabelleServer>,
+    info: ServerInfo,
+}
+
+struct PoolInner {
+    servers: Vec<PoolServer>,
+    /// Number of live sessions and running jobs on each server
+    load: Mutex<Vec<usize>>,
+    /// Options of the form `name=value` added to every session
+    options: Vec<String>,
+}
+
+/// A pool of Isabelle server processes that spreads sessions across them by load.
+///
+/// The servers are named after the [ServerBuilder] name with an index and a unique suffix appended, see [ServerBuilder::unique_name],
+/// e.g., `isabelle-0-4711-18c1b7f0a2e-0`, such that pools never share servers.
+/// The load of a server is the number of live sessions and running `use_theories` jobs on it.
+/// New sessions are started on the least loaded server.
+///
+/// All servers are shut down together by [ServerPool::shutdown], or when the last reference to the pool is dropped.
+#[derive(Clone)]
+pub struct ServerPool {
+    inner: Arc<PoolInner>,
+}
+
+impl ServerPool {
+    /// Starts `config.size` servers configured by `builder`, in parallel.
+    ///
+    /// If one of the servers fails to start, the servers started so far are shut down and the error is returned.
+    pub async fn start(
+        builder: &ServerBuilder,
+        config: ServerPoolConfig,
+    ) -> Result<Self, ServerError> {
+        let tasks = (0..config.size.max(1))
+            .map(|i| {
+                let mut builder = builder.clone();
+                let name = format!("{}-{}", builder.server_name(), i);
+                builder.name(&name).unique_name(true);
+                let timeout = config.startup_timeout;
+                tokio::spawn(async move { builder.run_async(timeout).await })
+            })
+            .collect::<Vec<_>>();
+
+        let mut servers = Vec::new();
+        let mut error = None;
+        for task in tasks {
+            match task.await.map_err(io::Error::other)? {
+                Ok(server) => servers.push(server),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        // Dropping the servers started so far shuts them down
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        let servers = servers
+            .into_iter()
+            .map(|server| PoolServer {
+                info: server.info(),
+                server: Mutex::new(server),
+            })
+            .collect::<Vec<_>>();
+        let load = Mutex::new(vec![0; servers.len()]);
+        let mut options = config
+            .session_options
+            .into_iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>();
+        options.sort();
+        Ok(Self {
+            inner: Arc::new(PoolInner {
+                servers,
+                load,
+                options,
+            }),
+        })
+    }
+
+    /// Returns the address and credentials of the servers in the pool.
+    pub fn servers(&self) -> Vec<ServerInfo> {
+        self.inner.servers.iter().map(|s| s.info.clone()).collect()
+    }
+
+    /// Returns the current load of each server in the pool.
+    pub fn load(&self) -> Vec<usize> {
+        self.inner.load.lock().unwrap().clone()
+    }
+
+    /// Starts a session on the least loaded server.
+    /// The session options of the pool are added to the options of `args`, where the latter take precedence.
+    ///
+    /// Returns an `io::Error` if communication with the server failed or the session could not be started.
+    pub async fn session_start(&self, args: &SessionStartArgs) -> io::Result<PoolSession> {
+        let load = LoadGuard::least_loaded(self.inner.clone());
+        let index = load.server;
+        let args = with_options(args, &self.inner.options);
+        let mut client = self.inner.servers[index].info.client();
+        let session = Session::start(&mut client, &args).await?;
+        log::trace!(
+            "Started session {} on server {}",
+            session.id(),
+            self.inner.servers[index].info.name
+        );
+        Ok(PoolSession {
+            session,
+            pool: self.inner.clone(),
+            server: index,
+            _load: load,
+        })
+    }
+
+    /// Shuts down all servers in the pool, in parallel.
+    /// Sessions still running on the servers are terminated.
+    pub async fn shutdown(&self) -> io::Result<()> {
+        let tasks = (0..self.inner.servers.len())
+            .map(|i| {
+                let inner = self.inner.clone();
+                tokio::task::spawn_blocking(move || inner.servers[i].server.lock().unwrap().exit())
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.map_err(io::Error::other)??;
+        }
+        Ok(())
+    }
+}
+
+/// A session running on a server of a [ServerPool].
+///
+/// The session counts towards the load of its server until it is stopped or dropped.
+pub struct PoolSession {
+    session: Session,
+    pool: Arc<PoolInner>,
+    server: usize,
+    _load: LoadGuard,
+}
+
+impl PoolSession {
+    /// Returns the session.
+    /// Theories are only used through [PoolSession::use_theories_with], such that they count towards the load of the server.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Returns the address and credentials of the server the session runs on.
+    pub fn server(&self) -> &ServerInfo {
+        &self.pool.servers[self.server].info
+    }
+
+    /// Runs `use_theories` in this session, counting towards the load of its server while it runs.
+    pub async fn use_theories_with(
+        &mut self,
+        args: UseTheoriesArgs,
+    ) -> io::Result<AsyncResult<UseTheoryResults, ()>> {
+        let _load = LoadGuard::new(self.pool.clone(), self.server);
+        self.session.use_theories_with(args).await
+    }
+
+    /// Adds the given theory files to the session, see [PoolSession::use_theories_with].
+    pub async fn use_theories(
+        &mut self,
+        theories: &[&str],
+    ) -> io::Result<AsyncResult<UseTheoryResults, ()>> {
+        let args = UseTheoriesArgs::for_session(self.session.id(), theories);
+        self.use_theories_with(args).await
+    }
+
+    /// Removes the given theories from the session.
+    pub async fn purge_theories(
+        &mut self,
+        theories: &[&str],
+    ) -> io::Result<SyncResult<PurgeTheoryResults, ()>> {
+        self.session.purge_theories(theories).await
+    }
+
+    /// Stops the session.
+    pub async fn stop(self) -> io::Result<AsyncResult<SessionStopResult, SessionStopResult>> {
+        self.session.stop().await
+    }
+}
+
+/// Counts towards the load of a server while it is alive.
+struct LoadGuard {
+    pool: Arc<PoolInner>,
+    server: usize,
+}
+
+impl LoadGuard {
+    fn new(pool: Arc<PoolInner>, server: usize) -> Self {
+        pool.load.lock().unwrap()[server] += 1;
+        Self { pool, server }
+    }
+
+    /// Counts towards the least loaded server, which is selected under the same lock, such that concurrent callers are spread.
+    fn least_loaded(pool: Arc<PoolInner>) -> Self {
+        let server = {
+            let mut load = pool.load.lock().unwrap();
+            let server = least_loaded(&load);
+            load[server] += 1;
+            server
+        };
+        Self { pool, server }
+    }
+}
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        self.pool.load.lock().unwrap()[self.server] -= 1;
+    }
+}
+
+/// Returns the index of the least loaded server, preferring lower indices.
+fn least_loaded(load: &[usize]) -> usize {
+    load.iter()
+        .enumerate()
+        .min_by_key(|(i, l)| (**l, *i))
+        .map_or(0, |(i, _)| i)
+}
+
+/// Returns `args` with `options` prepended to its options, such that the options of `args` take precedence.
+fn with_options(args: &SessionStartArgs, options: &[String]) -> SessionStartArgs {
+    let mut args = args.clone();
+    if !options.is_empty() {
+        let mut merged = options.to_vec();
+        merged.extend(args.build.options.take().unwrap_or_default());
+        args.build.options = Some(merged);
+    }
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::process::OptionsBuilder;
+    use serial_test::serial;
+
+    #[test]
+    fn test_least_loaded() {
+        assert_eq!(least_loaded(&[2, 1, 1]), 1);
+        assert_eq!(least_loaded(&[0, 0]), 0);
+        assert_eq!(least_loaded(&[3, 4, 0]), 2);
+    }
+
+    #[test]
+    fn test_with_options() {
+        let args = SessionStartArgs::session("HOL").options(["threads=2"]);
+        let merged = with_options(
+            &args,
+            &["threads=4".to_owned(), "quick_and_dirty=true".to_owned()],
+        );
+        assert_eq!(
+            merged.build.options.unwrap(),
+            vec!["threads=4", "quick_and_dirty=true", "threads=2"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_guards_spread() {
+        let inner = Arc::new(PoolInner {
+            servers: Vec::new(),
+            load: Mutex::new(vec![0; 4]),
+            options: Vec::new(),
+        });
+        let tasks = (0..8)
+            .map(|_| {
+                let inner = inner.clone();
+                tokio::spawn(async move { LoadGuard::least_loaded(inner) })
+            })
+            .collect::<Vec<_>>();
+        let mut guards = Vec::new();
+        for task in tasks {
+            guards.push(task.await.unwrap());
+        }
+        assert_eq!(*inner.load.lock().unwrap(), vec![2, 2, 2, 2]);
+        drop(guards);
+        assert_eq!(*inner.load.lock().unwrap(), vec![0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_spread_sessions() {
+        let mut builder = ServerBuilder::new();
+        builder.name("pool");
+        let mut options = OptionsBuilder::new();
+        options.threads(2);
+        let config = ServerPoolConfig {
+            size: 2,
+            session_options: options.into(),
+            ..Default::default()
+        };
+        let pool = ServerPool::start(&builder, config).await.unwrap();
+        let names = pool
+            .servers()
+            .into_iter()
+            .map(|s| s.name)
+            .collect::<Vec<_>>();
+        assert!(names[0].starts_with("pool-0-"));
+        assert!(names[1].starts_with("pool-1-"));
+
+        let mut first = pool
+            .session_start(&SessionStartArgs::session("HOL"))
+            .await
+            .unwrap();
+        let second = pool
+            .session_start(&SessionStartArgs::session("HOL"))
+            .await
+            .unwrap();
+        assert_ne!(first.server().port, second.server().port);
+        assert_eq!(pool.load(), vec![1, 1]);
+
+        let res = first
+            .use_theories(&["~~/src/HOL/Examples/Drinker"])
+            .await
+            .unwrap();
+        assert!(res.finished().ok);
+
+        first.stop().await.unwrap();
+        second.stop().await.unwrap();
+        assert_eq!(pool.load(), vec![0, 0]);
+        pool.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rusqlite::{params, Connection, OpenFlags};
+
+use super::ServerInfo;
+use crate::installation::Installation;
+
+/// Time a registered server may take to answer the handshake and an `echo`.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Returns the location of the servers registry of `installation` (`$ISABELLE_HOME_USER/servers.db`).
+fn database(installation: &Installation) -> io::Result<PathBuf> {
+    let home_user = match installation.isolated_home() {
+        Some(home) => home.to_path_buf(),
+        None => PathBuf::from(installation.getenv("ISABELLE_HOME_USER")?),
+    };
+    Ok(home_user.join("servers.db"))
+}
+
+fn open(db: &Path) -> io::Result<Connection> {
+    Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_WRITE).map_err(io::Error::other)
+}
+
+/// Returns all entries of the servers registry of the default installation, see [registered_servers_with].
+pub fn registered_servers() -> io::Result<Vec<ServerInfo>> {
+    registered_servers_with(&Installation::default())
+}
+
+/// Returns all entries of the servers registry of `installation`, including those of servers that are no longer running.
+/// In contrast to [super::list_servers_with], servers are not checked for liveness.
+pub fn registered_servers_with(installation: &Installation) -> io::Result<Vec<ServerInfo>> {
+    let db = database(installation)?;
+    if !db.exists() {
+        return Ok(Vec::new());
+    }
+    read_entries(&open(&db)?).map_err(io::Error::other)
+}
+
+fn read_entries(con: &Connection) -> rusqlite::Result<Vec<ServerInfo>> {
+    let mut stmt = con.prepare("SELECT name, port, password FROM isabelle_servers")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(ServerInfo {
+            name: row.get(0)?,
+            host: "127.0.0.1".to_owned(),
+            port: row.get(1)?,
+            password: row.get(2)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Removes stale entries from the servers registry of the default installation, see [remove_stale_servers_with].
+pub fn remove_stale_servers() -> io::Result<Vec<ServerInfo>> {
+    remove_stale_servers_with(&Installation::default())
+}
+
+/// Removes the entries of the servers registry of `installation` whose server refuses connections or rejects the password,
+/// e.g., because it crashed or because its port has been taken by another process.
+/// Returns the removed entries.
+///
+/// Live servers are left untouched, as are servers that accept connections but do not answer in time, which may just be busy.
+/// An entry is only removed if it has not been replaced by a new server with the same name in the meantime.
+pub fn remove_stale_servers_with(installation: &Installation) -> io::Result<Vec<ServerInfo>> {
+    let db = database(installation)?;
+    if !db.exists() {
+        return Ok(Vec::new());
+    }
+    let stale = registered_servers_with(installation)?
+        .into_iter()
+        .filter(|info| match info.client().echo_blocking(PROBE_TIMEOUT) {
+            Ok(()) => false,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionRefused | io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                log::debug!("Server {} on port {} is stale: {}", info.name, info.port, e);
+                true
+            }
+            Err(e) => {
+                log::debug!(
+                    "Keeping server {} on port {}, it did not answer: {}",
+                    info.name,
+                    info.port,
+                    e
+                );
+                false
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let con = open(&db)?;
+    for info in &stale {
+        con.execute(
+            "DELETE FROM isabelle_servers WHERE name = ?1 AND port = ?2 AND password = ?3",
+            params![info.name, info.port, info.password],
+        )
+        .map_err(io::Error::other)?;
+        log::info!("Removed stale server {} from the registry", info.name);
+    }
+    Ok(stale)
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::test_util::{self, fake_installation};
+    use std::io::{BufRead, BufReader, Write};
+
+    /// Serves a single connection answering the handshake and one command with `answer`.
+    fn fake_server(answer: &'static [u8]) -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (con, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(con.try_clone().unwrap());
+            let mut writer = con;
+            let mut line = String::new();
+            for _ in 0..2 {
+                reader.read_line(&mut line).unwrap();
+                writer.write_all(answer).unwrap();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_remove_stale_servers() {
+        // The directory of the executable serves as `ISABELLE_HOME_USER`
+        let installation = fake_installation("isabelle-client-registry", "dirname \"$0\"");
+        let home = test_util::home(&installation).to_path_buf();
+        let home_user = installation.executable().parent().unwrap().to_path_buf();
+        assert!(registered_servers_with(&installation).unwrap().is_empty());
+
+        let dead = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead_port = dead.local_addr().unwrap().port();
+        drop(dead);
+        let live_port = fake_server(b"OK\n");
+        let denied_port = fake_server(b"ERROR\n");
+        // Accepts connections, but never answers
+        let busy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy_port = busy.local_addr().unwrap().port();
+
+        let con = Connection::open(home_user.join("servers.db")).unwrap();
+        con.execute_batch(
+            "CREATE TABLE isabelle_servers (name TEXT PRIMARY KEY, port INTEGER, password TEXT)",
+        )
+        .unwrap();
+        for (name, port) in [
+            ("dead", dead_port),
+            ("live", live_port),
+            ("denied", denied_port),
+            ("busy", busy_port),
+        ] {
+            con.execute(
+                "INSERT INTO isabelle_servers VALUES (?1, ?2, 'secret')",
+                params![name, port],
+            )
+            .unwrap();
+        }
+
+        let removed = remove_stale_servers_with(&installation)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name)
+            .collect::<Vec<_>>();
+        assert_eq!(removed, vec!["dead", "denied"]);
+        let names = registered_servers_with(&installation)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["live", "busy"]);
+        drop(busy);
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+}
@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use tokio::sync::broadcast;
+
+use super::{IsabelleServer, ServerBuilder, ServerError, ServerInfo};
+use crate::client::{args::SessionStartArgs, session::Session, IsabelleClient};
+
+/// Configuration of a [Supervisor].
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    /// Interval in which the server is checked for liveness.
+    pub check_interval: Duration,
+    /// Time the server may take to print its address after it has been (re)started.
+    pub startup_timeout: Duration,
+    /// Number of restarts after which the supervisor gives up (0 = unlimited).
+    pub max_restarts: usize,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(1),
+            startup_timeout: Duration::from_secs(60),
+            max_restarts: 0,
+        }
+    }
+}
+
+/// Events published by a [Supervisor], see [Supervisor::subscribe].
+#[derive(Debug, Clone)]
+pub enum SupervisorEvent {
+    /// The server has died and is being restarted
+    Died,
+    /// The server has been restarted
+    Restarted {
+        /// Address and credentials of the new server
+        server: ServerInfo,
+        /// Ids of the restored sessions by their registered name
+        sessions: HashMap<String, String>,
+    },
+    /// The server could not be restarted, the supervisor has stopped watching it
+    Failed(String),
+}
+
+struct SupervisedSession {
+    args: SessionStartArgs,
+    /// The running session, `None` if it could not be restored
+    session: Option<Session>,
+}
+
+struct State {
+    sessions: HashMap<String, SupervisedSession>,
+    /// The supervised server, taken on shutdown
+    server: Option<IsabelleServer>,
+    restarts: usize,
+    stopped: bool,
+}
+
+struct SupervisorInner {
+    builder: ServerBuilder,
+    config: SupervisorConfig,
+    state: Mutex<State>,
+    events: broadcast::Sender<SupervisorEvent>,
+}
+
+/// Watches an Isabelle server and restarts it with the same [ServerBuilder] configuration if it dies.
+///
+/// Sessions registered with the supervisor are started again on the new server.
+/// As the new server has different credentials and the sessions have new ids, clients should [Supervisor::subscribe]
+/// to be notified of restarts, or obtain [Supervisor::client] and [Supervisor::session_id] again after an error.
+///
+/// The server is watched by a background task, which requires a tokio runtime.
+/// It stops when the supervisor is dropped or shut down.
+pub struct Supervisor {
+    inner: Arc<SupervisorInner>,
+}
+
+impl Supervisor {
+    /// Runs a server configured by `builder` and starts watching it.
+    pub async fn start(
+        builder: ServerBuilder,
+        config: SupervisorConfig,
+    ) -> Result<Self, ServerError> {
+        let server = builder.run_async(config.startup_timeout).await?;
+        let (events, _) = broadcast::channel(16);
+        let inner = Arc::new(SupervisorInner {
+            builder,
+            config,
+            state: Mutex::new(State {
+                sessions: HashMap::new(),
+                server: Some(server),
+                restarts: 0,
+                stopped: false,
+            }),
+            events,
+        });
+        tokio::spawn(watch(Arc::downgrade(&inner)));
+        Ok(Self { inner })
+    }
+
+    /// Returns the address and credentials of the current server.
+    pub fn info(&self) -> ServerInfo {
+        let state = self.inner.state.lock().unwrap();
+        state
+            .server
+            .as_ref()
+            .expect("Server already shut down")
+            .info()
+    }
+
+    /// Returns a client connected to the current server.
+    pub fn client(&self) -> IsabelleClient {
+        self.info().client()
+    }
+
+    /// Returns the number of times the server has been restarted.
+    pub fn restarts(&self) -> usize {
+        self.inner.state.lock().unwrap().restarts
+    }
+
+    /// Returns a receiver for the events of this supervisor.
+    pub fn subscribe(&self) -> broadcast::Receiver<SupervisorEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Starts a session with `args` and registers it under `name`, such that it is restored whenever the server is restarted.
+    /// Returns the id of the session.
+    ///
+    /// Returns an `io::Error` of kind `AlreadyExists` if a session with that name is already registered or being registered,
+    /// of kind `NotFound` if the session is unregistered before it has started, or if the session could not be started.
+    pub async fn register_session(&self, name: &str, args: SessionStartArgs) -> io::Result<String> {
+        {
+            // Reserve the name while the session starts
+            let mut state = self.inner.state.lock().unwrap();
+            if state.sessions.contains_key(name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Session {} is already registered", name),
+                ));
+            }
+            state.sessions.insert(
+                name.to_owned(),
+                SupervisedSession {
+                    args: args.clone(),
+                    session: None,
+                },
+            );
+        }
+
+        let session = match Session::start(&mut self.client(), &args).await {
+            Ok(session) => session,
+            Err(e) => {
+                let mut state = self.inner.state.lock().unwrap();
+                if state
+                    .sessions
+                    .get(name)
+                    .is_some_and(|s| s.session.is_none())
+                {
+                    state.sessions.remove(name);
+                }
+                return Err(e);
+            }
+        };
+
+        let restored = {
+            let mut state = self.inner.state.lock().unwrap();
+            match state.sessions.get_mut(name) {
+                // A restart in the meantime has already restored the session
+                Some(SupervisedSession {
+                    session: Some(restored),
+                    ..
+                }) => Some(restored.id().to_owned()),
+                Some(supervised) => {
+                    let id = session.id().to_owned();
+                    supervised.session = Some(session);
+                    return Ok(id);
+                }
+                // Unregistered in the meantime
+                None => None,
+            }
+        };
+        // The session is superfluous, its server may be gone already
+        if let Err(e) = session.stop().await {
+            log::debug!("Could not stop superfluous session {}: {}", name, e);
+        }
+        restored.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Session {} was unregistered while starting", name),
+            )
+        })
+    }
+
+    /// Returns the id of the session registered under `name`.
+    /// Returns `None` if there is no such session or if it could not be restored after a restart.
+    pub fn session_id(&self, name: &str) -> Option<String> {
+        let state = self.inner.state.lock().unwrap();
+        state
+            .sessions
+            .get(name)
+            .and_then(|s| s.session.as_ref())
+            .map(|s| s.id().to_owned())
+    }
+
+    /// Stops the session registered under `name` and removes it from the supervisor.
+    pub async fn unregister_session(&self, name: &str) -> io::Result<()> {
+        let removed = self.inner.state.lock().unwrap().sessions.remove(name);
+        if let Some(session) = removed.and_then(|s| s.session) {
+            session.stop().await?;
+        }
+        Ok(())
+    }
+
+    /// Stops watching the server, stops all registered sessions, and exits the server.
+    pub async fn shutdown(self) -> io::Result<()> {
+        let sessions = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.stopped = true;
+            state.sessions.drain().collect::<Vec<_>>()
+        };
+        for session in sessions.into_iter().filter_map(|(_, s)| s.session) {
+            session.stop().await?;
+        }
+        let server = self.inner.state.lock().unwrap().server.take();
+        match server {
+            Some(mut server) => server.exit_async().await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl SupervisorInner {
+    /// Restarts the dead server and restores the registered sessions.
+    async fn restart(&self) -> Result<(), ServerError> {
+        let (registered, dead_process) = {
+            let mut state = self.state.lock().unwrap();
+            state.restarts += 1;
+            let max_restarts = self.config.max_restarts;
+            if max_restarts > 0 && state.restarts > max_restarts {
+                return Err(ServerError::Io(io::Error::other(format!(
+                    "Server was restarted {} times",
+                    max_restarts
+                ))));
+            }
+            // The sessions died with the server
+            for supervised in state.sessions.values_mut() {
+                if let Some(session) = supervised.session.take() {
+                    session.forget();
+                }
+            }
+            let registered = state
+                .sessions
+                .iter()
+                .map(|(name, s)| (name.clone(), s.args.clone()))
+                .collect::<Vec<_>>();
+            (
+                registered,
+                state.server.as_mut().and_then(|s| s.handle.take()),
+            )
+        };
+        // Kill what survived of the dead server, such as Poly/ML processes, and reap its process
+        if let Some(process) = dead_process {
+            if let Err(e) = process.kill_tree_async().await {
+                log::warn!("Could not kill the processes of the dead server: {}", e);
+            }
+        }
+
+        let server = self.builder.run_async(self.config.startup_timeout).await?;
+        let mut client = IsabelleClient::for_server(&server);
+        let mut restored = Vec::new();
+        for (name, args) in registered {
+            match Session::start(&mut client, &args).await {
+                Ok(session) => restored.push((name, session)),
+                Err(e) => log::warn!("Could not restore session {}: {}", name, e),
+            }
+        }
+
+        let info = server.info();
+        let mut ids = HashMap::new();
+        let mut dead = {
+            let mut state = self.state.lock().unwrap();
+            for (name, session) in restored {
+                // Sessions unregistered in the meantime are dropped, which stops them
+                if let Some(supervised) = state.sessions.get_mut(&name) {
+                    ids.insert(name, session.id().to_owned());
+                    supervised.session = Some(session);
+                }
+            }
+            state.server.replace(server)
+        };
+        if let Some(dead) = dead.as_mut() {
+            dead.set_shutdown_on_drop(false);
+        }
+
+        log::info!("Restarted server {} on port {}", info.name, info.port);
+        let _ = self.events.send(SupervisorEvent::Restarted {
+            server: info,
+            sessions: ids,
+        });
+        Ok(())
+    }
+}
+
+/// Checks the server in regular intervals and restarts it if it has died.
+async fn watch(inner: Weak<SupervisorInner>) {
+    loop {
+        let interval = match inner.upgrade() {
+            Some(inner) => inner.config.check_interval,
+            None => return,
+        };
+        tokio::time::sleep(interval).await;
+
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let (alive, port) = {
+            let mut state = inner.state.lock().unwrap();
+            if state.stopped {
+                return;
+            }
+            match state.server.as_mut() {
+                Some(server) => (server.process_alive(), server.port()),
+                None => return,
+            }
+        };
+        // Probe servers not started by the supervisor outside the lock
+        let alive = match alive {
+            Some(alive) => alive,
+            None => tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok(),
+        };
+        if alive {
+            continue;
+        }
+
+        log::warn!("Server died, restarting it");
+        let _ = inner.events.send(SupervisorEvent::Died);
+        if let Err(e) = inner.restart().await {
+            log::error!("Could not restart server: {}", e);
+            let _ = inner.events.send(SupervisorEvent::Failed(e.to_string()));
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::exit;
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_restart_restores_sessions() {
+        let mut builder = ServerBuilder::new();
+        builder.name("supervised").unique_name(true);
+        let config = SupervisorConfig {
+            check_interval: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let supervisor = Supervisor::start(builder, config).await.unwrap();
+        let mut events = supervisor.subscribe();
+        let port = supervisor.info().port;
+        let id = supervisor
+            .register_session("hol", SessionStartArgs::session("HOL"))
+            .await
+            .unwrap();
+
+        exit(&supervisor.info().name).unwrap();
+
+        let sessions = loop {
+            match events.recv().await.unwrap() {
+                SupervisorEvent::Restarted { server, sessions } => {
+                    assert_ne!(server.port, port);
+                    break sessions;
+                }
+                SupervisorEvent::Died => continue,
+                SupervisorEvent::Failed(e) => panic!("{}", e),
+            }
+        };
+        assert_ne!(sessions["hol"], id);
+        assert_eq!(
+            supervisor.session_id("hol").as_ref(),
+            Some(&sessions["hol"])
+        );
+        assert_eq!(supervisor.restarts(), 1);
+
+        supervisor.shutdown().await.unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_restart_reaps_dead_server() {
+        // The server leaves a child behind in its process group when it dies
+        let installation = crate::test_util::fake_installation(
+            "isabelle-client-supervisor",
+            concat!(
+                "case \"$*\" in *-x) exit 0 ;; esac\n",
+                "dir=\"$(dirname \"$0\")\"\n",
+                "sleep 30 &\n",
+                "echo $! > \"$dir/child\"\n",
+                "echo $$ > \"$dir/pid\"\n",
+                "echo 'server \"fake\" = 127.0.0.1:4711 (password \"secret\")'\n",
+                "wait",
+            ),
+        );
+        let dir = installation.executable().parent().unwrap().to_path_buf();
+        let mut builder = ServerBuilder::new();
+        builder.name("fake").installation(installation);
+        let config = SupervisorConfig {
+            check_interval: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let supervisor = Supervisor::start(builder, config).await.unwrap();
+        let mut events = supervisor.subscribe();
+        let read_pid = |name| std::fs::read_to_string(dir.join(name)).unwrap();
+        let (pid, child) = (read_pid("pid"), read_pid("child"));
+
+        let status = std::process::Command::new("kill")
+            .arg("-9")
+            .arg(pid.trim())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        loop {
+            match events.recv().await.unwrap() {
+                SupervisorEvent::Restarted { .. } => break,
+                SupervisorEvent::Died => continue,
+                SupervisorEvent::Failed(e) => panic!("{}", e),
+            }
+        }
+
+        // The server process has been reaped, its child has been killed
+        let stat = |pid: &str| std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(stat(&pid).is_err());
+        let mut child_alive = true;
+        for _ in 0..50 {
+            child_alive = stat(&child).is_ok_and(|s| !s.contains(") Z "));
+            if !child_alive {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!child_alive);
+        assert_ne!(read_pid("pid"), pid);
+
+        supervisor.shutdown().await.unwrap();
+    }
+}
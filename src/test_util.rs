@@ -0,0 +1,37 @@
+//! Helpers shared by the tests of several modules.
+
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::installation::Installation;
+use crate::server::unique_name;
+
+/// Creates a fresh directory in the temp dir, named `name` with a unique suffix.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(unique_name(name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Creates a fake installation in a fresh directory, whose `bin/isabelle` executable runs `script`.
+/// The script finds the directory of the executable using `$(dirname "$0")`.
+pub(crate) fn fake_installation(name: &str, script: &str) -> Installation {
+    let bin = temp_dir(name).join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let exe = bin.join("isabelle");
+    std::fs::write(&exe, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+    Installation::from_path(&exe).unwrap()
+}
+
+/// Returns the directory containing the `bin` directory of a fake installation.
+pub(crate) fn home(installation: &Installation) -> &Path {
+    installation
+        .executable()
+        .parent()
+        .unwrap()
+        .parent()
+        .unwrap()
+}